@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::aarect::{Xyrect, Xzrect, Yzrect};
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Point;
+
+/// Axis-aligned box made of six rectangles
+pub struct Aabox {
+    box_min: Point,
+    box_max: Point,
+    sides: HittableList,
+}
+
+impl Aabox {
+    #[allow(dead_code)]
+    pub fn from(p0: Point, p1: Point, mat: Arc<dyn Material>) -> Self {
+        let mut sides = HittableList::new();
+
+        sides.push(Arc::new(Xyrect::from(
+            p0.x,
+            p1.x,
+            p0.y,
+            p1.y,
+            p1.z,
+            mat.clone(),
+        )));
+        sides.push(Arc::new(Xyrect::from(
+            p0.x,
+            p1.x,
+            p0.y,
+            p1.y,
+            p0.z,
+            mat.clone(),
+        )));
+
+        sides.push(Arc::new(Xzrect::from(
+            p0.x,
+            p1.x,
+            p0.z,
+            p1.z,
+            p1.y,
+            mat.clone(),
+        )));
+        sides.push(Arc::new(Xzrect::from(
+            p0.x,
+            p1.x,
+            p0.z,
+            p1.z,
+            p0.y,
+            mat.clone(),
+        )));
+
+        sides.push(Arc::new(Yzrect::from(
+            p0.y,
+            p1.y,
+            p0.z,
+            p1.z,
+            p1.x,
+            mat.clone(),
+        )));
+        sides.push(Arc::new(Yzrect::from(p0.y, p1.y, p0.z, p1.z, p0.x, mat)));
+
+        Aabox {
+            box_min: p0,
+            box_max: p1,
+            sides,
+        }
+    }
+}
+
+impl Hittable for Aabox {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.sides.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
+        Some(Aabb::from(self.box_min, self.box_max))
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.sides.materials()
+    }
+}
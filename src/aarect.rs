@@ -1,4 +1,5 @@
-use std::rc::Rc;
+use rand::prelude::*;
+use std::sync::Arc;
 
 use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
@@ -12,12 +13,12 @@ pub struct Xyrect {
     y0: f64,
     y1: f64,
     k: f64,
-    mat: Rc<dyn Material>,
+    mat: Arc<dyn Material>,
 }
 
 impl Xyrect {
     #[allow(dead_code)]
-    pub fn from(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, mat: Rc<dyn Material>) -> Self {
+    pub fn from(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, mat: Arc<dyn Material>) -> Self {
         Xyrect {
             x0,
             x1,
@@ -40,9 +41,18 @@ impl Hittable for Xyrect {
         if x < self.x0 || x > self.x1 || y < self.y0 || y > self.y1 {
             return None;
         }
+        let u = (x - self.x0) / (self.x1 - self.x0);
+        let v = (y - self.y0) / (self.y1 - self.y0);
         let p = r.at(t);
         let outward_normal = Vec3::from(0.0, 0.0, 1.0);
-        Some(HitRecord::from(r, p, t, outward_normal, self.mat.clone()))
+        let (dpdu, dpdv) = (
+            Vec3::from(self.x1 - self.x0, 0.0, 0.0),
+            Vec3::from(0.0, self.y1 - self.y0, 0.0),
+        );
+        Some(
+            HitRecord::from(r, p, t, u, v, outward_normal, self.mat.clone())
+                .with_tangents(dpdu, dpdv),
+        )
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
@@ -53,6 +63,27 @@ impl Hittable for Xyrect {
             Point::from(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![self.mat.clone()]
+    }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    fn sample_area(&self) -> Option<HitRecord> {
+        let mut rng = thread_rng();
+        let x = rng.gen_range(self.x0..self.x1);
+        let y = rng.gen_range(self.y0..self.y1);
+        Some(HitRecord::on_surface(
+            Point::from(x, y, self.k),
+            (x - self.x0) / (self.x1 - self.x0),
+            (y - self.y0) / (self.y1 - self.y0),
+            Vec3::from(0.0, 0.0, 1.0),
+            self.mat.clone(),
+        ))
+    }
 }
 
 pub struct Xzrect {
@@ -61,12 +92,12 @@ pub struct Xzrect {
     z0: f64,
     z1: f64,
     k: f64,
-    mat: Rc<dyn Material>,
+    mat: Arc<dyn Material>,
 }
 
 impl Xzrect {
     #[allow(dead_code)]
-    pub fn from(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, mat: Rc<dyn Material>) -> Self {
+    pub fn from(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, mat: Arc<dyn Material>) -> Self {
         Xzrect {
             x0,
             x1,
@@ -89,9 +120,18 @@ impl Hittable for Xzrect {
         if x < self.x0 || x > self.x1 || z < self.z0 || z > self.z1 {
             return None;
         }
+        let u = (x - self.x0) / (self.x1 - self.x0);
+        let v = (z - self.z0) / (self.z1 - self.z0);
         let p = r.at(t);
         let outward_normal = Vec3::from(0.0, 1.0, 0.0);
-        Some(HitRecord::from(r, p, t, outward_normal, self.mat.clone()))
+        let (dpdu, dpdv) = (
+            Vec3::from(self.x1 - self.x0, 0.0, 0.0),
+            Vec3::from(0.0, 0.0, self.z1 - self.z0),
+        );
+        Some(
+            HitRecord::from(r, p, t, u, v, outward_normal, self.mat.clone())
+                .with_tangents(dpdu, dpdv),
+        )
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
@@ -102,6 +142,27 @@ impl Hittable for Xzrect {
             Point::from(self.x1, self.k + 0.0001, self.z1),
         ))
     }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![self.mat.clone()]
+    }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.z1 - self.z0)
+    }
+
+    fn sample_area(&self) -> Option<HitRecord> {
+        let mut rng = thread_rng();
+        let x = rng.gen_range(self.x0..self.x1);
+        let z = rng.gen_range(self.z0..self.z1);
+        Some(HitRecord::on_surface(
+            Point::from(x, self.k, z),
+            (x - self.x0) / (self.x1 - self.x0),
+            (z - self.z0) / (self.z1 - self.z0),
+            Vec3::from(0.0, 1.0, 0.0),
+            self.mat.clone(),
+        ))
+    }
 }
 
 pub struct Yzrect {
@@ -110,12 +171,12 @@ pub struct Yzrect {
     z0: f64,
     z1: f64,
     k: f64,
-    mat: Rc<dyn Material>,
+    mat: Arc<dyn Material>,
 }
 
 impl Yzrect {
     #[allow(dead_code)]
-    pub fn from(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, mat: Rc<dyn Material>) -> Self {
+    pub fn from(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, mat: Arc<dyn Material>) -> Self {
         Yzrect {
             y0,
             y1,
@@ -138,9 +199,18 @@ impl Hittable for Yzrect {
         if y < self.y0 || y > self.y1 || z < self.z0 || z > self.z1 {
             return None;
         }
+        let u = (y - self.y0) / (self.y1 - self.y0);
+        let v = (z - self.z0) / (self.z1 - self.z0);
         let p = r.at(t);
         let outward_normal = Vec3::from(1.0, 0.0, 0.0);
-        Some(HitRecord::from(r, p, t, outward_normal, self.mat.clone()))
+        let (dpdu, dpdv) = (
+            Vec3::from(0.0, self.y1 - self.y0, 0.0),
+            Vec3::from(0.0, 0.0, self.z1 - self.z0),
+        );
+        Some(
+            HitRecord::from(r, p, t, u, v, outward_normal, self.mat.clone())
+                .with_tangents(dpdu, dpdv),
+        )
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
@@ -151,4 +221,25 @@ impl Hittable for Yzrect {
             Point::from(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![self.mat.clone()]
+    }
+
+    fn area(&self) -> f64 {
+        (self.y1 - self.y0) * (self.z1 - self.z0)
+    }
+
+    fn sample_area(&self) -> Option<HitRecord> {
+        let mut rng = thread_rng();
+        let y = rng.gen_range(self.y0..self.y1);
+        let z = rng.gen_range(self.z0..self.z1);
+        Some(HitRecord::on_surface(
+            Point::from(self.k, y, z),
+            (y - self.y0) / (self.y1 - self.y0),
+            (z - self.z0) / (self.z1 - self.z0),
+            Vec3::from(1.0, 0.0, 0.0),
+            self.mat.clone(),
+        ))
+    }
 }
@@ -0,0 +1,439 @@
+use crate::hittable::{self, HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::integrator::{Integrator, World};
+use crate::ray::Ray;
+use crate::vec3::{self, Color, Point, Vec3};
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+}
+
+// A vertex of a camera or light subpath. Densities are measured per unit area at the
+// vertex: `pdf_fwd` for the subpath generating it and `pdf_rev` for the opposite subpath
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    p: Point,
+    rec: Option<HitRecord>,
+    beta: Color, // Throughput of the subpath up to this vertex
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    delta: bool, // Scattering at the vertex is specular
+}
+
+impl Vertex {
+    fn camera(p: Point) -> Self {
+        Vertex {
+            kind: VertexKind::Camera,
+            p,
+            rec: None,
+            beta: Color::from(1.0, 1.0, 1.0),
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            delta: false,
+        }
+    }
+
+    // Light emitted towards `to`
+    fn emitted(&self, to: &Vertex, r: &Ray) -> Color {
+        match &self.rec {
+            Some(rec) => rec.mat.emitted(&r.spawn(to.p, self.p - to.p), rec),
+            None => Color::new(),
+        }
+    }
+
+    // Cosine factor for light leaving or arriving along `w`. The camera and scattering
+    // inside volumes have none
+    fn abs_cos(&self, w: Vec3) -> f64 {
+        match &self.rec {
+            Some(rec) if !rec.normal.near_zero() => {
+                vec3::dot(&rec.normal, &vec3::unit_vector(w)).abs()
+            }
+            _ => 1.0,
+        }
+    }
+
+    // Converts a solid angle density for the direction from this vertex towards `next`
+    // into an area density at `next`
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        match next.kind {
+            VertexKind::Camera => pdf / distance_squared,
+            _ => pdf * next.abs_cos(w) / distance_squared,
+        }
+    }
+
+    // BSDF of the vertex for light travelling from `to` (the light side) to `from` (the
+    // camera side)
+    fn bsdf(&self, from: Point, to: Point, r: &Ray) -> Color {
+        let rec = match &self.rec {
+            Some(rec) => rec,
+            None => return Color::new(),
+        };
+
+        let r_in = r.spawn(from, self.p - from);
+        let rec = rec.facing(&r_in);
+        let dir = to - self.p;
+        let cosine = self.abs_cos(dir);
+        if cosine < 1.0e-8 {
+            return Color::new();
+        }
+        rec.mat.eval(&r_in, &rec, dir) / cosine
+    }
+
+    // Area density at `next` of sampling it from this vertex after arriving from `prev`
+    fn pdf(&self, prev: Option<&Vertex>, next: &Vertex, r: &Ray) -> f64 {
+        match self.kind {
+            VertexKind::Camera => 0.0,
+            VertexKind::Light => self.pdf_light(next),
+            VertexKind::Surface => {
+                let (prev, rec) = match (prev, &self.rec) {
+                    (Some(prev), Some(rec)) => (prev, rec),
+                    _ => return 0.0,
+                };
+                let r_in = r.spawn(prev.p, self.p - prev.p);
+                let rec = rec.facing(&r_in);
+                let pdf = rec.mat.pdf(&r_in, &rec, next.p - self.p);
+                self.convert_density(pdf, next)
+            }
+        }
+    }
+
+    // Area density at `next` of emitting light towards it from this vertex
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        let pdf = hittable::emission_pdf(self.abs_cos(next.p - self.p));
+        self.convert_density(pdf, next)
+    }
+
+    // Area density of light sampling picking this point on a light
+    fn pdf_light_origin(&self, from: &Vertex, lights: &HittableList) -> f64 {
+        let pdf = lights.area_pdf_value(from.p, self.p - from.p);
+        from.convert_density(pdf, self)
+    }
+}
+
+fn is_black(c: Color) -> bool {
+    c.x == 0.0 && c.y == 0.0 && c.z == 0.0
+}
+
+fn geometry_term(a: &Vertex, b: &Vertex) -> f64 {
+    let w = b.p - a.p;
+    a.abs_cos(w) * b.abs_cos(w) / w.length_squared()
+}
+
+fn transmittance(world: &World, a: Point, b: Point, r: &Ray) -> f64 {
+    let d = b - a;
+    let distance = d.length();
+    world.transmittance(&r.spawn(a, d / distance), distance - 0.001)
+}
+
+/// Bidirectional path tracer connecting every prefix of a camera subpath with every
+/// prefix of a light subpath, weighted with the power heuristic. Strategies that connect
+/// light subpaths directly to the camera are not used
+pub struct BidirectionalPathTracer {
+    pub max_depth: i32,
+}
+
+impl BidirectionalPathTracer {
+    // Extends `path` by following scattered rays from its last vertex, returning the
+    // background light found if the ray escapes. Light subpaths are `adjoint`, so their
+    // BSDFs are evaluated with the light arriving from the previous vertex
+    fn random_walk(
+        &self,
+        world: &World,
+        mut ray: Ray,
+        mut beta: Color,
+        mut pdf_dir: f64,
+        adjoint: bool,
+        path: &mut Vec<Vertex>,
+    ) -> Color {
+        // Light subpaths skip the camera vertex
+        let max_vertices = match adjoint {
+            true => self.max_depth as usize + 1,
+            false => self.max_depth as usize + 2,
+        };
+        while path.len() < max_vertices {
+            let rec = match world.objects.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    return match adjoint {
+                        true => Color::new(),
+                        false => beta * world.escaped(&ray),
+                    };
+                }
+            };
+
+            let prev = path.len() - 1;
+            let mut vertex = Vertex {
+                kind: VertexKind::Surface,
+                p: rec.p,
+                rec: Some(rec),
+                beta,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+                delta: false,
+            };
+            vertex.pdf_fwd = path[prev].convert_density(pdf_dir, &vertex);
+            path.push(vertex);
+            if path.len() >= max_vertices {
+                break;
+            }
+
+            let vertex = &path[prev + 1];
+            let rec = vertex.rec.as_ref().unwrap();
+            let srec = match rec.mat.scatter(&ray, rec) {
+                Some(srec) => srec,
+                None => break,
+            };
+            let dir = srec.ray.dir;
+
+            match srec.pdf {
+                Some(pdf) => {
+                    beta = match adjoint {
+                        true => {
+                            let f = vertex.bsdf(vertex.p + dir, path[prev].p, &ray);
+                            beta * f * (vertex.abs_cos(dir) / pdf)
+                        }
+                        false => beta * srec.attenuation,
+                    };
+
+                    // Density of sampling the previous vertex when arriving along the
+                    // scattered direction instead
+                    let r_rev = ray.spawn(vertex.p + dir, -dir);
+                    let rec_rev = rec.facing(&r_rev);
+                    let pdf_rev = rec.mat.pdf(&r_rev, &rec_rev, path[prev].p - vertex.p);
+                    let pdf_rev = vertex.convert_density(pdf_rev, &path[prev]);
+
+                    path[prev].pdf_rev = pdf_rev;
+                    pdf_dir = pdf;
+                }
+                None => {
+                    beta = beta * srec.attenuation;
+                    path[prev].pdf_rev = 0.0;
+                    path[prev + 1].delta = true;
+                    pdf_dir = 0.0;
+                }
+            }
+            ray = srec.ray.inherit(&ray);
+        }
+
+        Color::new()
+    }
+
+    fn light_subpath(&self, world: &World, r: &Ray) -> Vec<Vertex> {
+        let (rec, pdf_pos) = match world.lights.sample_point() {
+            Some(sample) => sample,
+            None => return Vec::new(),
+        };
+
+        let direction = hittable::random_emission_direction(rec.normal);
+        let light = Vertex {
+            kind: VertexKind::Light,
+            p: rec.p,
+            beta: rec.mat.emitted(r, &rec) / pdf_pos,
+            rec: Some(rec),
+            pdf_fwd: pdf_pos,
+            pdf_rev: 0.0,
+            delta: false,
+        };
+        let cosine = light.abs_cos(direction);
+        let pdf_dir = hittable::emission_pdf(cosine);
+        let beta = light.beta * (cosine / pdf_dir);
+        let ray = r.spawn(light.p, direction);
+
+        let mut path = vec![light];
+        if pdf_dir > 0.0 {
+            self.random_walk(world, ray, beta, pdf_dir, true, &mut path);
+        }
+        path
+    }
+
+    // Contribution of the path made of the first `s` light and `t` camera vertices
+    fn connect(
+        &self,
+        world: &World,
+        light: &[Vertex],
+        camera: &[Vertex],
+        s: usize,
+        t: usize,
+        r: &Ray,
+    ) -> Color {
+        let pt = &camera[t - 1];
+        let pt_minus = &camera[t - 2];
+        let mut sampled = None;
+
+        let contribution = match s {
+            // The camera subpath found a light by itself
+            0 => pt.beta * pt.emitted(pt_minus, r),
+            // Connect to a newly sampled point on a light
+            1 => {
+                if pt.delta {
+                    return Color::new();
+                }
+                let (rec, pdf_pos) = match world.lights.sample_point() {
+                    Some(sample) => sample,
+                    None => return Color::new(),
+                };
+                let light = Vertex {
+                    kind: VertexKind::Light,
+                    p: rec.p,
+                    beta: rec.mat.emitted(r, &rec) / pdf_pos,
+                    rec: Some(rec),
+                    pdf_fwd: pdf_pos,
+                    pdf_rev: 0.0,
+                    delta: false,
+                };
+                let c = pt.beta
+                    * pt.bsdf(pt_minus.p, light.p, r)
+                    * light.beta
+                    * geometry_term(pt, &light);
+                if is_black(c) {
+                    return Color::new();
+                }
+                let c = c * transmittance(world, pt.p, light.p, r);
+                sampled = Some(light);
+                c
+            }
+            _ => {
+                let qs = &light[s - 1];
+                if qs.delta || pt.delta {
+                    return Color::new();
+                }
+                let c = qs.beta
+                    * qs.bsdf(pt.p, light[s - 2].p, r)
+                    * geometry_term(qs, pt)
+                    * pt.bsdf(pt_minus.p, qs.p, r)
+                    * pt.beta;
+                if is_black(c) {
+                    return Color::new();
+                }
+                c * transmittance(world, qs.p, pt.p, r)
+            }
+        };
+
+        if is_black(contribution) {
+            return Color::new();
+        }
+        contribution * self.mis_weight(world, light, camera, sampled.as_ref(), s, t, r)
+    }
+
+    // Power heuristic weight of the (s, t) strategy against all other strategies that
+    // could have produced the same path, found by walking the ratios of the densities
+    // along the path
+    #[allow(clippy::too_many_arguments)]
+    fn mis_weight(
+        &self,
+        world: &World,
+        light: &[Vertex],
+        camera: &[Vertex],
+        sampled: Option<&Vertex>,
+        s: usize,
+        t: usize,
+        r: &Ray,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+
+        // Work on copies of the densities, updated for the connection made by this strategy
+        let density = |v: &Vertex| (v.pdf_fwd, v.pdf_rev, v.delta);
+        let mut lv: Vec<(f64, f64, bool)> = light[..s].iter().map(density).collect();
+        let mut cv: Vec<(f64, f64, bool)> = camera[..t].iter().map(density).collect();
+
+        let qs = match s {
+            0 => None,
+            1 => sampled,
+            _ => Some(&light[s - 1]),
+        };
+        if let (1, Some(qs)) = (s, qs) {
+            lv[0] = density(qs);
+        }
+        let qs_minus = match s {
+            0 | 1 => None,
+            _ => Some(&light[s - 2]),
+        };
+        let pt = &camera[t - 1];
+        let pt_minus = &camera[t - 2];
+
+        // The connection vertices can always be connected
+        cv[t - 1].2 = false;
+        if s > 0 {
+            lv[s - 1].2 = false;
+        }
+
+        cv[t - 1].1 = match qs {
+            Some(qs) => qs.pdf(qs_minus, pt, r),
+            None => pt.pdf_light_origin(pt_minus, world.lights),
+        };
+        cv[t - 2].1 = match qs {
+            Some(qs) => pt.pdf(Some(qs), pt_minus, r),
+            None => pt.pdf_light(pt_minus),
+        };
+        if let Some(qs) = qs {
+            lv[s - 1].1 = pt.pdf(Some(pt_minus), qs, r);
+        }
+        if let (Some(qs), Some(qs_minus)) = (qs, qs_minus) {
+            lv[s - 2].1 = qs.pdf(Some(pt), qs_minus, r);
+        }
+
+        // Zero densities come from specular vertices, which cancel out
+        let remap = |f: f64| if f != 0.0 { f } else { 1.0 };
+        let mut sum = 0.0;
+
+        // Strategies with shorter camera subpaths, down to two camera vertices
+        let mut ri = 1.0;
+        for i in (2..t).rev() {
+            let r = remap(cv[i].1) / remap(cv[i].0);
+            ri *= r * r;
+            if !cv[i].2 && !cv[i - 1].2 {
+                sum += ri;
+            }
+        }
+
+        // Strategies with shorter light subpaths
+        let mut ri = 1.0;
+        for i in (0..s).rev() {
+            let r = remap(lv[i].1) / remap(lv[i].0);
+            ri *= r * r;
+            let delta_before = i > 0 && lv[i - 1].2;
+            if !lv[i].2 && !delta_before {
+                sum += ri;
+            }
+        }
+
+        1.0 / (1.0 + sum)
+    }
+}
+
+impl Integrator for BidirectionalPathTracer {
+    fn li(&self, r: &Ray, world: &World) -> Color {
+        // Connections evaluate BSDFs without ray differentials, so textures are looked
+        // up unfiltered along the camera subpath too, for every strategy to agree
+        let r = &r.spawn(r.orig, r.dir);
+        let mut camera = vec![Vertex::camera(r.orig)];
+        let beta = Color::from(1.0, 1.0, 1.0);
+        let mut color = self.random_walk(world, *r, beta, 0.0, false, &mut camera);
+
+        let light = self.light_subpath(world, r);
+
+        for t in 2..=camera.len() {
+            // A single light vertex is always sampled anew, so s = 1 works without a light
+            // subpath
+            for s in 0..=light.len().max(1) {
+                if (s + t - 2) as i32 > self.max_depth {
+                    break;
+                }
+                color = color + self.connect(world, &light, &camera, s, t, r);
+            }
+        }
+
+        color
+    }
+}
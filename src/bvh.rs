@@ -1,20 +1,21 @@
 use rand::prelude::*;
 use std::cmp::Ordering;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::aabb::{surrounding_box, Aabb};
 use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
+use crate::material::Material;
 
 pub struct BvhNode {
-    pub left: Rc<dyn Hittable>,
-    pub right: Rc<dyn Hittable>,
+    pub left: Arc<dyn Hittable>,
+    pub right: Arc<dyn Hittable>,
     pub bbox: Aabb,
 }
 
 impl BvhNode {
     pub fn from(
-        src_objects: &[Rc<dyn Hittable>],
+        src_objects: &[Arc<dyn Hittable>],
         start: usize,
         end: usize,
         time0: f64,
@@ -25,7 +26,7 @@ impl BvhNode {
         let mut rng = thread_rng();
         let axis = rng.gen_range(0..=2);
 
-        let comparator = |a: &Rc<dyn Hittable>, b: &Rc<dyn Hittable>| bbox_compare(a, b, axis);
+        let comparator = |a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>| bbox_compare(a, b, axis);
 
         let object_span = end - start;
 
@@ -44,10 +45,10 @@ impl BvhNode {
                 right = objects[start].clone();
             }
         } else {
-            objects.sort_by(comparator);
+            objects[start..end].sort_by(comparator);
             let mid = start + object_span / 2;
-            left = Rc::new(BvhNode::from(&objects, start, mid, time0, time1));
-            right = Rc::new(BvhNode::from(&objects, mid, end, time0, time1));
+            left = Arc::new(BvhNode::from(&objects, start, mid, time0, time1));
+            right = Arc::new(BvhNode::from(&objects, mid, end, time0, time1));
         }
 
         let bbox_left = left.bounding_box(time0, time1);
@@ -72,6 +73,11 @@ impl Hittable for BvhNode {
         }
 
         let rec_left = self.left.hit(r, t_min, t_max);
+        // Leaves holding a single object store it as both children. Asking it twice
+        // would give media two chances to scatter
+        if Arc::ptr_eq(&self.left, &self.right) {
+            return rec_left;
+        }
         let t = match &rec_left {
             Some(rec) => rec.t,
             None => t_max,
@@ -84,12 +90,54 @@ impl Hittable for BvhNode {
         }
     }
 
+    fn hit_surface(&self, r: &crate::ray::Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return None;
+        }
+
+        let rec_left = self.left.hit_surface(r, t_min, t_max);
+        if Arc::ptr_eq(&self.left, &self.right) {
+            return rec_left;
+        }
+        let t = match &rec_left {
+            Some(rec) => rec.t,
+            None => t_max,
+        };
+        let rec_right = self.right.hit_surface(r, t_min, t);
+
+        match rec_right {
+            Some(rec) => Some(rec),
+            None => rec_left,
+        }
+    }
+
+    fn transmittance(&self, r: &crate::ray::Ray, t_min: f64, t_max: f64) -> f64 {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return 1.0;
+        }
+        // Leaves holding a single object store it as both children
+        let left = self.left.transmittance(r, t_min, t_max);
+        match Arc::ptr_eq(&self.left, &self.right) {
+            true => left,
+            false => left * self.right.transmittance(r, t_min, t_max),
+        }
+    }
+
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
         Some(self.bbox)
     }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        let mut materials = self.left.materials();
+        // Leaves holding a single object store it as both children
+        if !Arc::ptr_eq(&self.left, &self.right) {
+            materials.extend(self.right.materials());
+        }
+        materials
+    }
 }
 
-fn bbox_compare(a: &Rc<dyn Hittable>, b: &Rc<dyn Hittable>, axis: i32) -> Ordering {
+fn bbox_compare(a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>, axis: i32) -> Ordering {
     let a_bbox = a.bounding_box(0.0, 0.0);
     let b_bbox = b.bounding_box(0.0, 0.0);
     let a_val = match a_bbox {
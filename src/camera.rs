@@ -1,6 +1,6 @@
 use rand::prelude::*;
 
-use crate::ray::Ray;
+use crate::ray::{Differentials, Ray};
 use crate::vec3::{self, Point, Vec3};
 
 pub struct Camera {
@@ -10,6 +10,7 @@ pub struct Camera {
     pub vertical: Vec3,
     pub u: Vec3,
     pub v: Vec3,
+    #[allow(dead_code)]
     pub w: Vec3,
     pub lens_radius: f64,
     pub time0: f64, // Shutter open time
@@ -58,16 +59,25 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
+    /// Ray through (s, t) on the viewport, with differentials for rays `ds` and `dt`
+    /// further along
+    pub fn get_ray(&self, s: f64, t: f64, ds: f64, dt: f64) -> Ray {
         let rd = self.lens_radius * Vec3::random_in_unit_disk();
         let offset = self.u * rd.x + self.v * rd.y;
+        let orig = self.origin + offset;
+        let dir = |s: f64, t: f64| {
+            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset
+        };
 
         let mut rng = rand::thread_rng();
 
-        Ray::from(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-            rng.gen_range(self.time0..self.time1),
-        )
+        let mut r = Ray::from(orig, dir(s, t), rng.gen_range(self.time0..self.time1));
+        r.differentials = Some(Differentials {
+            rx_orig: orig,
+            rx_dir: dir(s + ds, t),
+            ry_orig: orig,
+            ry_dir: dir(s, t + dt),
+        });
+        r
     }
 }
@@ -0,0 +1,128 @@
+use rand::prelude::*;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::{Material, Volumetric};
+use crate::phase::{Isotropic, PhaseFunction};
+use crate::ray::Ray;
+use crate::vec3::Color;
+
+/// Volume of constant density filling a closed, convex boundary object. Rays passing
+/// through are scattered at exponentially distributed distances
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    /// Medium scattering isotropically
+    #[allow(dead_code)]
+    pub fn from(boundary: Arc<dyn Hittable>, density: f64, albedo: Color) -> Self {
+        Self::from_phase(boundary, density, albedo, Arc::new(Isotropic {}))
+    }
+
+    #[allow(dead_code)]
+    pub fn from_phase(
+        boundary: Arc<dyn Hittable>,
+        density: f64,
+        albedo: Color,
+        phase: Arc<dyn PhaseFunction>,
+    ) -> Self {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function: Arc::new(Volumetric::from(albedo, phase)),
+        }
+    }
+}
+
+/// Part of the ray between `t_min` and `t_max` inside a closed, convex boundary, even
+/// if it starts inside
+pub fn boundary_interval(
+    boundary: &dyn Hittable,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64)> {
+    let rec1 = boundary.hit(r, -f64::INFINITY, f64::INFINITY)?;
+    let rec2 = boundary.hit(r, rec1.t + 0.0001, f64::INFINITY)?;
+
+    let t1 = rec1.t.max(t_min).max(0.0);
+    let t2 = rec2.t.min(t_max);
+    match t1 < t2 {
+        true => Some((t1, t2)),
+        false => None,
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t1, t2) = boundary_interval(self.boundary.as_ref(), r, t_min, t_max)?;
+
+        let ray_length = r.dir.length();
+        let distance_inside_boundary = (t2 - t1) * ray_length;
+        let hit_distance = self.neg_inv_density * random::<f64>().ln();
+        if hit_distance > distance_inside_boundary {
+            return None;
+        }
+
+        let t = t1 + hit_distance / ray_length;
+        Some(HitRecord::in_volume(
+            r.at(t),
+            t,
+            self.phase_function.clone(),
+        ))
+    }
+
+    fn hit_surface(&self, _: &Ray, _: f64, _: f64) -> Option<HitRecord> {
+        None
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        match boundary_interval(self.boundary.as_ref(), r, t_min, t_max) {
+            // Beer-Lambert law
+            Some((t1, t2)) => ((t2 - t1) * r.dir.length() / self.neg_inv_density).exp(),
+            None => 1.0,
+        }
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.boundary.bounding_box(time0, time1)
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![self.phase_function.clone()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::{Point, Vec3};
+
+    #[test]
+    fn free_flights_follow_beer_lambert() {
+        let white = Color::from(1.0, 1.0, 1.0);
+        let boundary = Arc::new(Sphere::from(
+            Point::new(),
+            1.0,
+            Arc::new(Lambertian::from(white)),
+        ));
+        let medium = ConstantMedium::from(boundary, 0.4, white);
+
+        // Through the middle of the sphere, with a direction that isn't a unit vector
+        let r = Ray::from(Point::from(-5.0, 0.0, 0.0), Vec3::from(2.0, 0.0, 0.0), 0.0);
+        let expected = (-0.4 * 2.0_f64).exp();
+        assert!((medium.transmittance(&r, 0.001, f64::INFINITY) - expected).abs() < 1e-9);
+
+        let n = 100_000;
+        let passed = (0..n)
+            .filter(|_| medium.hit(&r, 0.001, f64::INFINITY).is_none())
+            .count();
+        assert!((passed as f64 / n as f64 - expected).abs() < 0.01);
+    }
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use std::io;
+
+use crate::aabb::Aabb;
+use crate::perlin::Perlin;
+use crate::vec3::Point;
+
+/// Spatially varying density of a participating medium
+pub trait Density: Send + Sync {
+    /// Extinction coefficient at `p`
+    fn density(&self, p: Point) -> f64;
+
+    /// Upper bound of `density` everywhere
+    fn max_density(&self) -> f64;
+}
+
+/// Densities sampled at the centers of the voxels of a regular grid stretched over
+/// `bbox`, interpolated trilinearly in between. Outside the box the density is zero
+pub struct VoxelGrid {
+    dims: [usize; 3],
+    values: Vec<f64>, // x varies fastest, then y, then z
+    bbox: Aabb,
+    max: f64,
+}
+
+impl VoxelGrid {
+    /// Grid with `values` multiplied by `scale`
+    pub fn from(dims: [usize; 3], values: Vec<f64>, bbox: Aabb, scale: f64) -> io::Result<Self> {
+        if dims.contains(&0) || voxel_count(dims) != Some(values.len()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "expected {}x{}x{} voxels but found {}",
+                    dims[0],
+                    dims[1],
+                    dims[2],
+                    values.len()
+                ),
+            ));
+        }
+
+        let values: Vec<f64> = values.iter().map(|v| (v * scale).max(0.0)).collect();
+        let max = values.iter().cloned().fold(0.0, f64::max);
+        Ok(VoxelGrid {
+            dims,
+            values,
+            bbox,
+            max,
+        })
+    }
+
+    /// Loads a text file holding the grid size `nx ny nz` followed by the voxel values,
+    /// separated by whitespace
+    pub fn load_ascii(path: &str, bbox: Aabb, scale: f64) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut tokens = text.split_whitespace();
+        let mut next = |what: &str| {
+            tokens
+                .next()
+                .ok_or_else(|| invalid_data(format!("{path}: missing {what}")))
+        };
+
+        let mut dims = [0; 3];
+        for n in dims.iter_mut() {
+            let token = next("grid size")?;
+            *n = token
+                .parse()
+                .map_err(|_| invalid_data(format!("{path}: invalid grid size '{token}'")))?;
+        }
+
+        let count = voxel_count(dims)
+            .ok_or_else(|| invalid_data(format!("{path}: grid size too large")))?;
+        // The values are pushed as they're read, so a bogus size can't allocate up front
+        let mut values = Vec::new();
+        for _ in 0..count {
+            let token = next("voxel value")?;
+            values.push(
+                token
+                    .parse()
+                    .map_err(|_| invalid_data(format!("{path}: invalid value '{token}'")))?,
+            );
+        }
+
+        Self::from(dims, values, bbox, scale)
+    }
+
+    /// Loads a headerless file of little-endian 32-bit floats for a grid of size `dims`
+    pub fn load_raw(path: &str, dims: [usize; 3], bbox: Aabb, scale: f64) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let values = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect();
+        Self::from(dims, values, bbox, scale)
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[(z * self.dims[1] + y) * self.dims[0] + x]
+    }
+}
+
+impl Density for VoxelGrid {
+    fn density(&self, p: Point) -> f64 {
+        let mut cell = [0; 3];
+        let mut frac = [0.0; 3];
+        for axis in 0..3 {
+            let extent = self.bbox.max[axis] - self.bbox.min[axis];
+            let local = (p[axis] - self.bbox.min[axis]) / extent;
+            if !(0.0..=1.0).contains(&local) {
+                return 0.0;
+            }
+
+            // Voxel values sit at the voxel centers, and are clamped at the edges
+            let n = self.dims[axis];
+            let x = (local * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            cell[axis] = (x.floor() as usize).min(n.saturating_sub(2));
+            frac[axis] = x - cell[axis] as f64;
+        }
+
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for axis in 0..3 {
+                index[axis] = (cell[axis] + offset[axis]).min(self.dims[axis] - 1);
+                weight *= match offset[axis] {
+                    0 => 1.0 - frac[axis],
+                    _ => frac[axis],
+                };
+            }
+            density += weight * self.voxel(index[0], index[1], index[2]);
+        }
+        density
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max
+    }
+}
+
+/// Cloudy density from Perlin turbulence, up to `max_density`
+pub struct NoiseDensity {
+    noise: Perlin,
+    frequency: f64,
+    max_density: f64,
+}
+
+impl NoiseDensity {
+    pub fn from(frequency: f64, max_density: f64) -> Self {
+        NoiseDensity {
+            noise: Perlin::new(),
+            frequency,
+            max_density,
+        }
+    }
+}
+
+impl Density for NoiseDensity {
+    fn density(&self, p: Point) -> f64 {
+        let turbulence = self.noise.turb(self.frequency * p, 7);
+        self.max_density * turbulence.min(1.0)
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max_density
+    }
+}
+
+// Number of voxels in a grid of size `dims`, or None if it overflows
+fn voxel_count(dims: [usize; 3]) -> Option<usize> {
+    dims[0].checked_mul(dims[1])?.checked_mul(dims[2])
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,246 @@
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+
+use crate::color::write_color;
+use crate::tile::Tile;
+use crate::vec3::Color;
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"TRTCKPT3";
+const CHECKPOINT_HEADER_BYTES: u64 = 24; // Magic, width, height and fingerprint
+const CHECKPOINT_PIXEL_BYTES: u64 = 36; // Sum, squared sum and sample count
+
+/// Running sums of the radiance samples taken for a pixel
+#[derive(Copy, Clone, Default)]
+pub struct Pixel {
+    pub sum: Color,
+    pub sum_sq: f64, // Sum of squared sample luminances
+    pub samples: u32,
+}
+
+impl Pixel {
+    pub fn add_sample(&mut self, color: Color) {
+        self.sum = self.sum + color;
+        self.sum_sq += luminance(color) * luminance(color);
+        self.samples += 1;
+    }
+
+    /// Standard error of the pixel mean, measured after the gamma 2 encoding of the
+    /// output image so that the same threshold works for dark and bright pixels
+    pub fn error(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        let mean = luminance(self.sum) / n;
+        let variance = ((self.sum_sq - mean * mean * n) / (n - 1.0)).max(0.0);
+        if variance == 0.0 {
+            return 0.0;
+        }
+        // d(sqrt(x)) = dx / (2 sqrt(x))
+        (variance / n).sqrt() / (2.0 * mean.max(1.0e-4).sqrt())
+    }
+}
+
+pub fn luminance(color: Color) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// Floating-point accumulation buffer holding the running sums of radiance samples for
+/// every pixel. Rows are stored top to bottom
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Pixel>,
+    pub fingerprint: u64, // Options the samples were taken with, see Options::fingerprint
+}
+
+impl Film {
+    pub fn from(width: usize, height: usize) -> Self {
+        Film {
+            width,
+            height,
+            pixels: vec![Pixel::default(); width * height],
+            fingerprint: 0,
+        }
+    }
+
+    /// Adds the sample sums of a rendered tile
+    pub fn add_tile(&mut self, tile: &Tile, pixels: &[Pixel]) {
+        for (row, y) in (tile.y0..tile.y1).enumerate() {
+            for (col, x) in (tile.x0..tile.x1).enumerate() {
+                let new = &pixels[row * tile.width() + col];
+                let pixel = &mut self.pixels[y * self.width + x];
+                pixel.sum = pixel.sum + new.sum;
+                pixel.sum_sq += new.sum_sq;
+                pixel.samples += new.samples;
+            }
+        }
+    }
+
+    pub fn write_ppm(&self, path: &str) -> io::Result<()> {
+        let f = File::create(path)?;
+        let mut writer = BufWriter::new(f);
+
+        let header = format!("P3\n{} {}\n255\n", self.width, self.height);
+        writer.write_all(header.as_bytes())?;
+
+        for pixel in &self.pixels {
+            write_color(&mut writer, pixel.sum, pixel.samples.max(1) as i32)?;
+        }
+        writer.flush()
+    }
+
+    /// Writes an image of the number of samples taken per pixel, from blue for
+    /// `min_samples` to red for `max_samples`
+    pub fn write_heatmap(&self, path: &str, min_samples: u32, max_samples: u32) -> io::Result<()> {
+        let f = File::create(path)?;
+        let mut writer = BufWriter::new(f);
+
+        let header = format!("P3\n{} {}\n255\n", self.width, self.height);
+        writer.write_all(header.as_bytes())?;
+
+        let range = max_samples.saturating_sub(min_samples).max(1) as f64;
+        for pixel in &self.pixels {
+            let t = (pixel.samples.saturating_sub(min_samples) as f64 / range).clamp(0.0, 1.0);
+            let heat = Color::from(t, 1.0 - (2.0 * t - 1.0).abs(), 1.0 - t);
+            // write_color gamma-corrects, so square the color to keep the ramp linear
+            write_color(&mut writer, heat * heat, 1)?;
+        }
+        writer.flush()
+    }
+
+    /// Saves the accumulation buffer and sample counts. The checkpoint is written to a
+    /// temporary file first so that a run killed mid-write keeps the previous checkpoint
+    pub fn save_checkpoint(&self, path: &str) -> io::Result<()> {
+        let tmp_path = format!("{path}.tmp");
+        let f = File::create(&tmp_path)?;
+        let mut writer = BufWriter::new(f);
+
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&(self.width as u32).to_le_bytes())?;
+        writer.write_all(&(self.height as u32).to_le_bytes())?;
+        writer.write_all(&self.fingerprint.to_le_bytes())?;
+        for pixel in &self.pixels {
+            writer.write_all(&pixel.sum.x.to_le_bytes())?;
+            writer.write_all(&pixel.sum.y.to_le_bytes())?;
+            writer.write_all(&pixel.sum.z.to_le_bytes())?;
+            writer.write_all(&pixel.sum_sq.to_le_bytes())?;
+            writer.write_all(&pixel.samples.to_le_bytes())?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn load_checkpoint(path: &str) -> io::Result<Self> {
+        let f = File::open(path)?;
+        let mut reader = BufReader::new(f);
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{path} is not a checkpoint file"),
+            ));
+        }
+
+        let width = read_u32(&mut reader)? as usize;
+        let height = read_u32(&mut reader)? as usize;
+        let fingerprint = read_u64(&mut reader)?;
+
+        // Check the size in the header against the file before allocating for it
+        let expected = (width as u64)
+            .checked_mul(height as u64)
+            .and_then(|count| count.checked_mul(CHECKPOINT_PIXEL_BYTES))
+            .and_then(|bytes| bytes.checked_add(CHECKPOINT_HEADER_BYTES));
+        let actual = reader.get_ref().metadata()?.len();
+        if expected != Some(actual) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{path}: size doesn't match a {width}x{height} checkpoint"),
+            ));
+        }
+
+        let mut film = Film::from(width, height);
+        film.fingerprint = fingerprint;
+        for pixel in film.pixels.iter_mut() {
+            pixel.sum = Color::from(
+                read_f64(&mut reader)?,
+                read_f64(&mut reader)?,
+                read_f64(&mut reader)?,
+            );
+            pixel.sum_sq = read_f64(&mut reader)?;
+            pixel.samples = read_u32(&mut reader)?;
+        }
+
+        Ok(film)
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let path = std::env::temp_dir().join("trt-film-round-trip.ckpt");
+        let path = path.to_str().unwrap();
+
+        let mut film = Film::from(3, 2);
+        film.fingerprint = 0x1234_5678_9abc_def0;
+        for (i, pixel) in film.pixels.iter_mut().enumerate() {
+            pixel.add_sample(Color::from(i as f64, 0.5, -1.0));
+            pixel.add_sample(Color::from(0.25, i as f64 * 2.0, 3.0));
+        }
+        film.save_checkpoint(path).unwrap();
+        let loaded = Film::load_checkpoint(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert_eq!(loaded.fingerprint, film.fingerprint);
+        for (a, b) in film.pixels.iter().zip(&loaded.pixels) {
+            assert_eq!((a.sum.x, a.sum.y, a.sum.z), (b.sum.x, b.sum.y, b.sum.z));
+            assert_eq!(a.sum_sq, b.sum_sq);
+            assert_eq!(a.samples, b.samples);
+        }
+    }
+
+    #[test]
+    fn checkpoint_with_bad_size_is_rejected() {
+        let path = std::env::temp_dir().join("trt-film-bad-size.ckpt");
+        let path = path.to_str().unwrap();
+
+        // A header claiming a huge image with no pixel data behind it
+        let mut bytes = CHECKPOINT_MAGIC.to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        fs::write(path, bytes).unwrap();
+        let result = Film::load_checkpoint(path);
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,20 +1,34 @@
-use std::rc::Rc;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vec3::{self, Point, Vec3};
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point,
-    pub normal: Vec3,
-    pub mat: Rc<dyn Material>,
+    pub normal: Vec3, // Zero for scattering inside a volume
+    pub mat: Arc<dyn Material>,
     pub t: f64,
+    pub u: f64, // Surface coordinates of the hit point
+    pub v: f64,
+    pub dpdu: Vec3, // Change of the hit point along u and v, zero when unknown
+    pub dpdv: Vec3,
     pub front_face: bool,
 }
 
 impl HitRecord {
-    pub fn from(r: &Ray, p: Point, t: f64, outward_normal: Vec3, mat: Rc<dyn Material>) -> Self {
+    pub fn from(
+        r: &Ray,
+        p: Point,
+        t: f64,
+        u: f64,
+        v: f64,
+        outward_normal: Vec3,
+        mat: Arc<dyn Material>,
+    ) -> Self {
         let front_face = vec3::dot(&r.dir, &outward_normal) < 0.0;
         let normal = match front_face {
             // ray is outside
@@ -27,12 +41,174 @@ impl HitRecord {
             normal,
             mat,
             t,
+            u,
+            v,
+            dpdu: Vec3::new(),
+            dpdv: Vec3::new(),
             front_face,
         }
     }
+
+    /// Sets the directions in which the surface coordinates increase
+    pub fn with_tangents(mut self, dpdu: Vec3, dpdv: Vec3) -> Self {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    /// Width in surface coordinates of the area seen by one sample of the ray that made
+    /// this hit, or zero when the ray has no differentials
+    pub fn footprint(&self, r: &Ray) -> f64 {
+        let (px, py) = match r
+            .differentials
+            .and_then(|d| d.at_plane(self.p, self.normal))
+        {
+            Some(points) => points,
+            None => return 0.0,
+        };
+
+        // Least squares fit of the offsets on the surface as steps along u and v
+        let a = vec3::dot(&self.dpdu, &self.dpdu);
+        let b = vec3::dot(&self.dpdu, &self.dpdv);
+        let c = vec3::dot(&self.dpdv, &self.dpdv);
+        let det = a * c - b * b;
+        if det.abs() < 1e-12 {
+            return 0.0;
+        }
+        let uv_step = |dp: Vec3| {
+            let (pu, pv) = (vec3::dot(&self.dpdu, &dp), vec3::dot(&self.dpdv, &dp));
+            let du = (c * pu - b * pv) / det;
+            let dv = (a * pv - b * pu) / det;
+            (du * du + dv * dv).sqrt()
+        };
+        uv_step(px - self.p).max(uv_step(py - self.p))
+    }
+
+    /// Copy of the record with the normal facing against `r`, which may come from the
+    /// other side of the surface than the ray that made the hit
+    pub fn facing(&self, r: &Ray) -> HitRecord {
+        let outward = match self.front_face {
+            true => self.normal,
+            false => -self.normal,
+        };
+        let mut rec = self.clone();
+        rec.front_face = vec3::dot(&r.dir, &outward) < 0.0;
+        rec.normal = match rec.front_face {
+            true => outward,
+            false => -outward,
+        };
+        rec
+    }
+
+    /// Record for a point sampled on a surface rather than hit by a ray. The normal
+    /// points outwards
+    pub fn on_surface(p: Point, u: f64, v: f64, normal: Vec3, mat: Arc<dyn Material>) -> Self {
+        HitRecord {
+            p,
+            normal,
+            mat,
+            t: 0.0,
+            u,
+            v,
+            dpdu: Vec3::new(),
+            dpdv: Vec3::new(),
+            front_face: true,
+        }
+    }
+
+    /// Record for a scattering event inside a volume, which has no surface normal
+    pub fn in_volume(p: Point, t: f64, mat: Arc<dyn Material>) -> Self {
+        HitRecord {
+            p,
+            normal: Vec3::new(),
+            mat,
+            t,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::new(),
+            dpdv: Vec3::new(),
+            front_face: true,
+        }
+    }
+}
+
+/// Samples the direction of light leaving an emitter with outward normal `normal`.
+/// Lights emit from both sides, so a side is picked and a cosine-weighted direction
+/// around it
+pub fn random_emission_direction(normal: Vec3) -> Vec3 {
+    let side = match rand::random::<bool>() {
+        true => normal,
+        false => -normal,
+    };
+    let direction = side + Vec3::random_unit_vector();
+    match direction.near_zero() {
+        true => side,
+        false => direction,
+    }
 }
 
-pub trait Hittable {
+/// Solid angle PDF of `random_emission_direction` for a direction at `cosine` to the normal
+pub fn emission_pdf(cosine: f64) -> f64 {
+    0.5 * cosine.abs() / PI
+}
+
+pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
+
+    /// Materials of the object and anything it holds, in the order they were added
+    fn materials(&self) -> Vec<Arc<dyn Material>>;
+
+    /// Like `hit`, but passes through participating media
+    fn hit_surface(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.hit(r, t_min, t_max)
+    }
+
+    /// Fraction of the light travelling along `r` between `t_min` and `t_max` that
+    /// participating media let through. Surfaces are left to `hit_surface`
+    fn transmittance(&self, _r: &Ray, _t_min: f64, _t_max: f64) -> f64 {
+        1.0
+    }
+
+    /// Surface area, or zero for objects that can't be sampled as lights
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    /// Uniformly samples a point on the surface
+    fn sample_area(&self) -> Option<HitRecord> {
+        None
+    }
+
+    /// Solid angle PDF of sampling direction `v` from `origin` with `random`
+    fn pdf_value(&self, origin: Point, v: Vec3) -> f64 {
+        self.area_pdf_value(origin, v)
+    }
+
+    /// Solid angle PDF of reaching the first surface hit along `v` from `origin` by
+    /// picking a point with `sample_area`
+    fn area_pdf_value(&self, origin: Point, v: Vec3) -> f64 {
+        let area = self.area();
+        if area <= 0.0 {
+            return 0.0;
+        }
+
+        match self.hit(&Ray::from(origin, v, 0.0), 0.001, f64::INFINITY) {
+            Some(rec) => {
+                // Convert the area density to a solid angle density
+                let distance_squared = rec.t * rec.t * v.length_squared();
+                let cosine = (vec3::dot(&v, &rec.normal) / v.length()).abs();
+                distance_squared / (cosine * area)
+            }
+            None => 0.0,
+        }
+    }
+
+    /// Samples a direction from `origin` towards a point on the surface
+    fn random(&self, origin: Point) -> Vec3 {
+        match self.sample_area() {
+            Some(rec) => rec.p - origin,
+            None => Vec3::from(1.0, 0.0, 0.0),
+        }
+    }
 }
@@ -1,11 +1,14 @@
-use std::rc::Rc;
+use rand::prelude::*;
+use std::sync::Arc;
 
 use crate::aabb::{surrounding_box, Aabb};
 use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
 use crate::ray::Ray;
+use crate::vec3::{Point, Vec3};
 
 pub struct HittableList {
-    pub objects: Vec<Rc<dyn Hittable>>,
+    pub objects: Vec<Arc<dyn Hittable>>,
 }
 
 impl HittableList {
@@ -15,9 +18,17 @@ impl HittableList {
         }
     }
 
-    pub fn push(&mut self, object: Rc<dyn Hittable>) {
+    pub fn push(&mut self, object: Arc<dyn Hittable>) {
         self.objects.push(object);
     }
+
+    /// Picks an object uniformly and a point uniformly on its surface, returning the
+    /// point with its area density
+    pub fn sample_point(&self) -> Option<(HitRecord, f64)> {
+        let object = self.objects.choose(&mut thread_rng())?;
+        let rec = object.sample_area()?;
+        Some((rec, 1.0 / (self.objects.len() as f64 * object.area())))
+    }
 }
 
 impl Hittable for HittableList {
@@ -34,6 +45,26 @@ impl Hittable for HittableList {
         record
     }
 
+    fn hit_surface(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut closest_hit = t_max;
+        let mut record = None;
+
+        for object in &self.objects {
+            if let Some(rec) = object.hit_surface(r, t_min, closest_hit) {
+                closest_hit = rec.t;
+                record = Some(rec);
+            }
+        }
+        record
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        self.objects
+            .iter()
+            .map(|object| object.transmittance(r, t_min, t_max))
+            .product()
+    }
+
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
         if self.objects.is_empty() {
             return None;
@@ -59,4 +90,44 @@ impl Hittable for HittableList {
 
         out_box
     }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.objects
+            .iter()
+            .flat_map(|object| object.materials())
+            .collect()
+    }
+
+    fn pdf_value(&self, origin: Point, v: Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+
+        // Every object is picked with equal probability by random
+        let weight = 1.0 / self.objects.len() as f64;
+        self.objects
+            .iter()
+            .map(|object| weight * object.pdf_value(origin, v))
+            .sum()
+    }
+
+    fn area_pdf_value(&self, origin: Point, v: Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+
+        // Light subpaths start on an object picked with equal probability
+        let weight = 1.0 / self.objects.len() as f64;
+        self.objects
+            .iter()
+            .map(|object| weight * object.area_pdf_value(origin, v))
+            .sum()
+    }
+
+    fn random(&self, origin: Point) -> Vec3 {
+        match self.objects.choose(&mut thread_rng()) {
+            Some(object) => object.random(origin),
+            None => Vec3::from(1.0, 0.0, 0.0),
+        }
+    }
 }
@@ -0,0 +1,350 @@
+use std::fs;
+use std::io;
+
+use crate::vec3::Color;
+
+/// How texel coordinates outside the image are mapped back into it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+}
+
+/// Image of linear colors, with the top row first
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    /// Loads a binary (P6) or text (P3) PPM file, converting its sRGB values to linear
+    pub fn load_ppm(path: &str) -> io::Result<Self> {
+        Self::read_ppm(path, srgb_to_linear)
+    }
+
+    /// Loads a PPM file holding data rather than colors, like a normal map, keeping
+    /// its values as they are
+    pub fn load_ppm_linear(path: &str) -> io::Result<Self> {
+        Self::read_ppm(path, |value| value)
+    }
+
+    // Loads a PPM file, mapping its values scaled to [0, 1] with `decode`
+    fn read_ppm(path: &str, decode: fn(f64) -> f64) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut pos = 0;
+
+        let mut header = [0usize; 3];
+        let magic = next_token(&bytes, &mut pos);
+        if magic != b"P3" && magic != b"P6" {
+            return Err(invalid_data(format!("{path}: not a PPM file")));
+        }
+        for (value, what) in header.iter_mut().zip(["width", "height", "maximum value"]) {
+            let token = String::from_utf8_lossy(next_token(&bytes, &mut pos)).to_string();
+            *value = token
+                .parse()
+                .map_err(|_| invalid_data(format!("{path}: invalid {what} '{token}'")))?;
+        }
+        let [width, height, max] = header;
+        if width == 0 || height == 0 || max == 0 || max > 65535 {
+            return Err(invalid_data(format!("{path}: invalid header")));
+        }
+
+        let count = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(3))
+            .ok_or_else(|| invalid_data(format!("{path}: image too large")))?;
+        let values: Vec<usize> = match magic {
+            b"P6" => {
+                // A single whitespace byte separates the header from the samples, which
+                // take two bytes each when the maximum value needs them
+                let size = match max < 256 {
+                    true => 1,
+                    false => 2,
+                };
+                let data = bytes.get(pos + 1..).unwrap_or_default();
+                data.chunks_exact(size)
+                    .take(count)
+                    .map(|b| match size {
+                        1 => b[0] as usize,
+                        _ => (b[0] as usize) << 8 | b[1] as usize,
+                    })
+                    .collect()
+            }
+            _ => (0..count)
+                .map_while(|_| {
+                    String::from_utf8_lossy(next_token(&bytes, &mut pos))
+                        .parse()
+                        .ok()
+                })
+                .collect(),
+        };
+        if values.len() != count {
+            return Err(invalid_data(format!(
+                "{path}: expected {count} samples but found {}",
+                values.len()
+            )));
+        }
+
+        let linear = |value: usize| decode(value.min(max) as f64 / max as f64);
+        let pixels = values
+            .chunks_exact(3)
+            .map(|c| Color::from(linear(c[0]), linear(c[1]), linear(c[2])))
+            .collect();
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Pixel at column `x` and row `y`, which may lie outside the image
+    pub fn texel(&self, x: i64, y: i64, wrap: Wrap) -> Color {
+        let fit = |i: i64, n: usize| match wrap {
+            Wrap::Repeat => i.rem_euclid(n as i64) as usize,
+            Wrap::Clamp => i.clamp(0, n as i64 - 1) as usize,
+        };
+        self.pixels[fit(y, self.height) * self.width + fit(x, self.width)]
+    }
+
+    /// Color at texture coordinates (u, v), with v pointing up, interpolated
+    /// bilinearly between the four closest pixel centers
+    pub fn bilinear(&self, u: f64, v: f64, wrap: Wrap) -> Color {
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0, wrap) * (1.0 - dx) + self.texel(x0 + 1, y0, wrap) * dx;
+        let bottom =
+            self.texel(x0, y0 + 1, wrap) * (1.0 - dx) + self.texel(x0 + 1, y0 + 1, wrap) * dx;
+        top * (1.0 - dy) + bottom * dy
+    }
+
+    // Image of half the size, rounded up so that odd rows and columns are kept. Each
+    // pixel averages the pixels of its 2x2 footprint that lie inside the image
+    fn downsample(&self) -> Self {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let xs = 2 * x..(2 * x + 2).min(self.width);
+                let ys = 2 * y..(2 * y + 2).min(self.height);
+                let count = xs.len() * ys.len();
+                let sum = ys
+                    .flat_map(|y| xs.clone().map(move |x| (x, y)))
+                    .fold(Color::new(), |sum, (x, y)| {
+                        sum + self.pixels[y * self.width + x]
+                    });
+                pixels.push(sum / count as f64);
+            }
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// An image with versions of it halving in size down to a single pixel, to look up
+/// colors averaged over areas of any size
+pub struct MipMap {
+    levels: Vec<Image>,
+}
+
+impl MipMap {
+    pub fn from(image: Image) -> Self {
+        let mut levels = vec![image];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+        MipMap { levels }
+    }
+
+    /// Color at (u, v) averaged over about `width` in texture coordinates, blending
+    /// between the two levels whose pixels are closest in size
+    pub fn trilinear(&self, u: f64, v: f64, width: f64, wrap: Wrap) -> Color {
+        let base = &self.levels[0];
+        let texels = width * base.width.max(base.height) as f64;
+        let level = match texels > 1.0 {
+            true => texels.log2().min((self.levels.len() - 1) as f64),
+            false => 0.0,
+        };
+
+        let lower = level.floor() as usize;
+        let t = level - lower as f64;
+        let color = self.levels[lower].bilinear(u, v, wrap);
+        match t > 0.0 {
+            true => (1.0 - t) * color + t * self.levels[lower + 1].bilinear(u, v, wrap),
+            false => color,
+        }
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.levels[0]
+    }
+}
+
+// Returns the next whitespace separated token of a PPM header, skipping comments
+fn next_token<'a>(bytes: &'a [u8], pos: &mut usize) -> &'a [u8] {
+    loop {
+        while *pos < bytes.len() && bytes[*pos].is_ascii_whitespace() {
+            *pos += 1;
+        }
+        if *pos < bytes.len() && bytes[*pos] == b'#' {
+            while *pos < bytes.len() && bytes[*pos] != b'\n' {
+                *pos += 1;
+            }
+            continue;
+        }
+        break;
+    }
+
+    let start = *pos;
+    while *pos < bytes.len() && !bytes[*pos].is_ascii_whitespace() {
+        *pos += 1;
+    }
+    &bytes[start..*pos]
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    match value <= 0.04045 {
+        true => value / 12.92,
+        false => ((value + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes `bytes` to a temporary file named after `name` and loads it back
+    fn load(name: &str, bytes: &[u8], decode: fn(&str) -> io::Result<Image>) -> io::Result<Image> {
+        let path = std::env::temp_dir().join(format!("trt-image-{name}.ppm"));
+        let path = path.to_str().unwrap();
+        fs::write(path, bytes).unwrap();
+        let image = decode(path);
+        fs::remove_file(path).unwrap();
+        image
+    }
+
+    fn assert_color(color: Color, expected: (f64, f64, f64)) {
+        let (x, y, z) = expected;
+        assert!(
+            (color.x - x).abs() < 1e-9 && (color.y - y).abs() < 1e-9 && (color.z - z).abs() < 1e-9,
+            "{color:?} against {expected:?}"
+        );
+    }
+
+    #[test]
+    fn text_ppm_with_comments() {
+        let ppm = b"P3\n# a comment\n2 1 # another\n4\n0 1 2\n3 4 9\n";
+        let image = load("text", ppm, Image::load_ppm_linear).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_color(image.texel(0, 0, Wrap::Clamp), (0.0, 0.25, 0.5));
+        // Values above the maximum are clamped to it
+        assert_color(image.texel(1, 0, Wrap::Clamp), (0.75, 1.0, 1.0));
+    }
+
+    #[test]
+    fn binary_ppm_with_one_and_two_byte_samples() {
+        let mut ppm = b"P6 1 2 255\n".to_vec();
+        ppm.extend_from_slice(&[0, 51, 255, 255, 0, 102]);
+        let image = load("binary", &ppm, Image::load_ppm_linear).unwrap();
+        assert_eq!((image.width, image.height), (1, 2));
+        assert_color(image.texel(0, 0, Wrap::Clamp), (0.0, 0.2, 1.0));
+        assert_color(image.texel(0, 1, Wrap::Clamp), (1.0, 0.0, 0.4));
+
+        let mut ppm = b"P6\n1 1\n1000\n".to_vec();
+        ppm.extend_from_slice(&[0, 250, 1, 244, 3, 232]);
+        let image = load("wide", &ppm, Image::load_ppm_linear).unwrap();
+        assert_color(image.texel(0, 0, Wrap::Clamp), (0.25, 0.5, 1.0));
+    }
+
+    #[test]
+    fn srgb_values_are_made_linear() {
+        let image = load("srgb", b"P3 1 1 255 0 188 255", Image::load_ppm).unwrap();
+        let color = image.texel(0, 0, Wrap::Clamp);
+        assert_eq!(color.x, 0.0);
+        assert!((color.y - 0.5).abs() < 0.01, "{color:?}");
+        assert_eq!(color.z, 1.0);
+    }
+
+    #[test]
+    fn broken_ppm_files_are_rejected() {
+        for (name, ppm) in [
+            ("magic", b"P5 1 1 255 0".as_slice()),
+            ("header", b"P3 1 x 255 0 0 0"),
+            ("max", b"P3 1 1 0 0 0 0"),
+            ("short", b"P3 2 1 255 0 0 0 0"),
+            ("truncated", b"P6 2 2 255\n\x00\x01\x02"),
+        ] {
+            let result = load(name, ppm, Image::load_ppm);
+            assert_eq!(
+                result.err().unwrap().kind(),
+                io::ErrorKind::InvalidData,
+                "{name}"
+            );
+        }
+    }
+
+    fn mean(image: &Image) -> Color {
+        image.pixels.iter().fold(Color::new(), |sum, &c| sum + c) / image.pixels.len() as f64
+    }
+
+    #[test]
+    fn mip_levels_keep_the_mean_color() {
+        let (width, height) = (16, 8);
+        let pixels = (0..width * height)
+            .map(|i| Color::from((i % 7) as f64, (i * i % 11) as f64, (i % 3) as f64))
+            .collect();
+        let mipmap = MipMap::from(Image {
+            width,
+            height,
+            pixels,
+        });
+
+        let base = mean(mipmap.image());
+        assert_eq!(mipmap.levels.len(), 5);
+        for level in &mipmap.levels {
+            let m = mean(level);
+            assert!((m - base).length() < 1e-9, "{m:?} against {base:?}");
+        }
+    }
+
+    #[test]
+    fn mip_levels_of_odd_sizes_keep_the_edges() {
+        let (width, height) = (5, 3);
+        // Only the last column is lit, which rounding the sizes down would drop
+        let pixels = (0..width * height)
+            .map(|i| match i % width == width - 1 {
+                true => Color::from(1.0, 1.0, 1.0),
+                false => Color::new(),
+            })
+            .collect();
+        let mipmap = MipMap::from(Image {
+            width,
+            height,
+            pixels,
+        });
+
+        let sizes: Vec<_> = mipmap.levels.iter().map(|l| (l.width, l.height)).collect();
+        assert_eq!(sizes, [(5, 3), (3, 2), (2, 1), (1, 1)]);
+        // The last column of the second level covers the lit column alone
+        assert_eq!(mipmap.levels[1].texel(2, 1, Wrap::Clamp).x, 1.0);
+        assert_eq!(mipmap.levels[1].texel(1, 0, Wrap::Clamp).x, 0.0);
+        assert!(mipmap.levels[3].texel(0, 0, Wrap::Clamp).x > 0.0);
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{Point, Vec3};
+
+// Rebuilds a hit record for the ray in world space from one found in object space,
+// moving directions with `transform`
+fn transformed(r: &Ray, rec: HitRecord, p: Point, transform: impl Fn(Vec3) -> Vec3) -> HitRecord {
+    let outward_normal = match rec.front_face {
+        true => transform(rec.normal),
+        false => -transform(rec.normal),
+    };
+    let (dpdu, dpdv) = (transform(rec.dpdu), transform(rec.dpdv));
+    HitRecord::from(r, p, rec.t, rec.u, rec.v, outward_normal, rec.mat).with_tangents(dpdu, dpdv)
+}
+
+/// Moves an object by `offset`
+pub struct Translate {
+    object: Arc<dyn Hittable>,
+    offset: Vec3,
+}
+
+impl Translate {
+    #[allow(dead_code)]
+    pub fn from(object: Arc<dyn Hittable>, offset: Vec3) -> Self {
+        Translate { object, offset }
+    }
+}
+
+impl Hittable for Translate {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let moved_r = Ray::from(r.orig - self.offset, r.dir, r.t);
+        let rec = self.object.hit(&moved_r, t_min, t_max)?;
+        let p = rec.p + self.offset;
+        Some(transformed(r, rec, p, |v| v))
+    }
+
+    fn hit_surface(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let moved_r = Ray::from(r.orig - self.offset, r.dir, r.t);
+        let rec = self.object.hit_surface(&moved_r, t_min, t_max)?;
+        let p = rec.p + self.offset;
+        Some(transformed(r, rec, p, |v| v))
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let moved_r = Ray::from(r.orig - self.offset, r.dir, r.t);
+        self.object.transmittance(&moved_r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let bbox = self.object.bounding_box(time0, time1)?;
+        Some(Aabb::from(bbox.min + self.offset, bbox.max + self.offset))
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.object.materials()
+    }
+}
+
+/// Rotates an object by `angle` degrees around the y axis
+pub struct RotateY {
+    object: Arc<dyn Hittable>,
+    sin_theta: f64,
+    cos_theta: f64,
+    bbox: Option<Aabb>,
+}
+
+impl RotateY {
+    #[allow(dead_code)]
+    pub fn from(object: Arc<dyn Hittable>, angle: f64) -> Self {
+        let radians = angle.to_radians();
+        let sin_theta = radians.sin();
+        let cos_theta = radians.cos();
+
+        // The box around the rotated corners of the object's box
+        let bbox = object.bounding_box(0.0, 1.0).map(|bbox| {
+            let mut min = Point::from(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+            let mut max = Point::from(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+            for corner in 0..8 {
+                let x = match corner & 1 {
+                    0 => bbox.min.x,
+                    _ => bbox.max.x,
+                };
+                let y = match corner & 2 {
+                    0 => bbox.min.y,
+                    _ => bbox.max.y,
+                };
+                let z = match corner & 4 {
+                    0 => bbox.min.z,
+                    _ => bbox.max.z,
+                };
+                let rotated = Point::from(
+                    cos_theta * x + sin_theta * z,
+                    y,
+                    -sin_theta * x + cos_theta * z,
+                );
+                min = Point::from(
+                    min.x.min(rotated.x),
+                    min.y.min(rotated.y),
+                    min.z.min(rotated.z),
+                );
+                max = Point::from(
+                    max.x.max(rotated.x),
+                    max.y.max(rotated.y),
+                    max.z.max(rotated.z),
+                );
+            }
+            Aabb::from(min, max)
+        });
+
+        RotateY {
+            object,
+            sin_theta,
+            cos_theta,
+            bbox,
+        }
+    }
+
+    // Rotates by the object's angle, or back by it when `inverse`
+    fn rotate(&self, v: Vec3, inverse: bool) -> Vec3 {
+        let sin_theta = match inverse {
+            true => -self.sin_theta,
+            false => self.sin_theta,
+        };
+        Vec3::from(
+            self.cos_theta * v.x + sin_theta * v.z,
+            v.y,
+            -sin_theta * v.x + self.cos_theta * v.z,
+        )
+    }
+}
+
+impl Hittable for RotateY {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rotated_r = Ray::from(self.rotate(r.orig, true), self.rotate(r.dir, true), r.t);
+        let rec = self.object.hit(&rotated_r, t_min, t_max)?;
+        let p = self.rotate(rec.p, false);
+        Some(transformed(r, rec, p, |v| self.rotate(v, false)))
+    }
+
+    fn hit_surface(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rotated_r = Ray::from(self.rotate(r.orig, true), self.rotate(r.dir, true), r.t);
+        let rec = self.object.hit_surface(&rotated_r, t_min, t_max)?;
+        let p = self.rotate(rec.p, false);
+        Some(transformed(r, rec, p, |v| self.rotate(v, false)))
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let rotated_r = Ray::from(self.rotate(r.orig, true), self.rotate(r.dir, true), r.t);
+        self.object.transmittance(&rotated_r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        self.object.materials()
+    }
+}
@@ -0,0 +1,430 @@
+use rand::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::bdpt::BidirectionalPathTracer;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::material::Material;
+use crate::options::Options;
+use crate::photon::PhotonMapper;
+use crate::ray::Ray;
+use crate::spectrum;
+use crate::vec3::{self, Color, Vec3};
+
+/// The parts of a scene needed to estimate light transport
+pub struct World<'a> {
+    pub objects: &'a dyn Hittable,
+    pub lights: &'a HittableList, // Emissive objects that can be sampled directly
+    pub background: Color,
+}
+
+impl World<'_> {
+    /// Fraction of the light travelling along `r` up to `t_max` that isn't blocked by a
+    /// surface or lost in participating media
+    pub fn transmittance(&self, r: &Ray, t_max: f64) -> f64 {
+        match self.objects.hit_surface(r, 0.001, t_max) {
+            Some(_) => 0.0,
+            None => self.objects.transmittance(r, 0.001, t_max),
+        }
+    }
+
+    /// Light arriving along a ray that leaves the scene
+    pub fn escaped(&self, r: &Ray) -> Color {
+        spectrum::color_at(self.background, r)
+    }
+}
+
+pub trait Integrator: Send + Sync {
+    /// Estimates the radiance arriving at the camera along `r`
+    fn li(&self, r: &Ray, world: &World) -> Color;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IntegratorKind {
+    Path,
+    Bidirectional,
+    PhotonMap,
+    Naive,
+    Direct,
+    AmbientOcclusion,
+    Normals,
+    Depth,
+    Uv,
+    MaterialId,
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(IntegratorKind::Path),
+            "bdpt" => Ok(IntegratorKind::Bidirectional),
+            "photon" => Ok(IntegratorKind::PhotonMap),
+            "naive" => Ok(IntegratorKind::Naive),
+            "direct" => Ok(IntegratorKind::Direct),
+            "ao" => Ok(IntegratorKind::AmbientOcclusion),
+            "normals" => Ok(IntegratorKind::Normals),
+            "depth" => Ok(IntegratorKind::Depth),
+            "uv" => Ok(IntegratorKind::Uv),
+            "material-id" => Ok(IntegratorKind::MaterialId),
+            _ => Err(format!("unknown integrator '{s}'")),
+        }
+    }
+}
+
+impl IntegratorKind {
+    /// Whether the integrator handles rays carrying wavelengths. Debug integrators
+    /// output false colors, and photons are traced in RGB
+    pub fn supports_spectral(self) -> bool {
+        matches!(
+            self,
+            IntegratorKind::Path
+                | IntegratorKind::Bidirectional
+                | IntegratorKind::Naive
+                | IntegratorKind::Direct
+        )
+    }
+}
+
+/// Creates the integrator selected by the options, doing any preprocessing of the world
+/// it needs. Distance based integrators are scaled to `scene_size`, the longest distance
+/// expected between the camera and a visible object. `materials` are those of the scene in
+/// the order they were added, which debug colors are picked by
+pub fn create(
+    options: &Options,
+    world: &World,
+    scene_size: f64,
+    materials: &[Arc<dyn Material>],
+) -> Box<dyn Integrator> {
+    let max_depth = options.max_depth;
+    match options.integrator {
+        IntegratorKind::Path => Box::new(PathTracer {
+            max_depth,
+            rr_depth: options.rr_depth,
+        }),
+        IntegratorKind::Bidirectional => Box::new(BidirectionalPathTracer { max_depth }),
+        IntegratorKind::PhotonMap => Box::new(PhotonMapper::from(
+            world,
+            max_depth,
+            options.photons,
+            options.caustic_photons,
+            options.gather_photons,
+            0.02 * scene_size,
+            options.threads,
+        )),
+        IntegratorKind::Naive => Box::new(NaivePathTracer { max_depth }),
+        IntegratorKind::Direct => Box::new(DirectLighting { max_depth }),
+        IntegratorKind::AmbientOcclusion => Box::new(AmbientOcclusion {
+            distance: 0.25 * scene_size,
+        }),
+        IntegratorKind::Normals => Box::new(Normals {}),
+        IntegratorKind::Depth => Box::new(Depth {
+            max_distance: scene_size,
+        }),
+        IntegratorKind::Uv => Box::new(Uv {}),
+        IntegratorKind::MaterialId => Box::new(MaterialId::from(materials)),
+    }
+}
+
+// Weight of a sample from a strategy with density `f` when a strategy with density `g`
+// could also have produced it
+pub fn power_heuristic(f: f64, g: f64) -> f64 {
+    let (f2, g2) = (f * f, g * g);
+    match f2 + g2 > 0.0 {
+        true => f2 / (f2 + g2),
+        false => 0.0,
+    }
+}
+
+// Estimates direct lighting at a hit by sampling a point on one of the lights and
+// tracing a shadow ray towards it, weighted against finding the light by BSDF sampling
+pub fn sample_lights(r: &Ray, rec: &HitRecord, world: &World) -> Color {
+    let direction = world.lights.random(rec.p);
+    let light_pdf = world.lights.pdf_value(rec.p, direction);
+    if light_pdf <= 0.0 {
+        return Color::new();
+    }
+
+    let f = rec.mat.eval(r, rec, direction);
+    if f.near_zero() {
+        return Color::new();
+    }
+    let weight = power_heuristic(light_pdf, rec.mat.pdf(r, rec, direction));
+
+    // Whatever surface the shadow ray hits first is the light reaching us from that
+    // direction, so an occluder contributes its own (usually zero) emission. Media in
+    // between attenuate it
+    let shadow = r.spawn(rec.p, direction);
+    match world.objects.hit_surface(&shadow, 0.001, f64::INFINITY) {
+        Some(light_rec) => {
+            let transmittance = world.objects.transmittance(&shadow, 0.001, light_rec.t);
+            let emitted = light_rec.mat.emitted(&shadow, &light_rec);
+            f * emitted * (transmittance * weight / light_pdf)
+        }
+        None => Color::new(),
+    }
+}
+
+// The image writer gamma-corrects, so debug values are squared to show up linearly
+fn debug_color(c: Color) -> Color {
+    c * c
+}
+
+/// Unidirectional path tracer with light sampling and Russian roulette
+pub struct PathTracer {
+    pub max_depth: i32,
+    pub rr_depth: i32, // Bounces before Russian roulette may terminate a path
+}
+
+impl Integrator for PathTracer {
+    // Traces a path from the camera, adding up the light found at every bounce weighted
+    // by the path throughput. After `rr_depth` bounces paths are randomly terminated with
+    // a probability based on their throughput, and the survivors are weighted up to keep
+    // the estimate unbiased
+    fn li(&self, r: &Ray, world: &World) -> Color {
+        let mut color = Color::new();
+        let mut throughput = Color::from(1.0, 1.0, 1.0);
+        let mut ray = *r;
+        let mut emission_weight = 1.0;
+
+        // Paths end after max_depth bounces, gathering no more light
+        for depth in 0..self.max_depth {
+            let rec = match world.objects.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    // If the ray hits nothing, add the background color
+                    color = color + throughput * world.escaped(&ray);
+                    break;
+                }
+            };
+
+            color = color + throughput * rec.mat.emitted(&ray, &rec) * emission_weight;
+
+            let srec = match rec.mat.scatter(&ray, &rec) {
+                Some(srec) => srec,
+                None => break,
+            };
+
+            // Non-specular surfaces also sample the lights directly, so light found by the
+            // scattered ray is weighted by multiple importance sampling
+            emission_weight = match srec.pdf {
+                Some(pdf) => {
+                    color = color + throughput * sample_lights(&ray, &rec, world);
+                    power_heuristic(pdf, world.lights.pdf_value(rec.p, srec.ray.dir))
+                }
+                None => 1.0,
+            };
+
+            throughput = throughput * srec.attenuation;
+            ray = srec.ray.inherit(&ray);
+
+            if depth + 1 >= self.rr_depth {
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+                if random::<f64>() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+        }
+
+        color
+    }
+}
+
+/// Path tracer that only finds light by following scattered rays, useful as a reference
+/// for the other integrators
+pub struct NaivePathTracer {
+    pub max_depth: i32,
+}
+
+impl Integrator for NaivePathTracer {
+    fn li(&self, r: &Ray, world: &World) -> Color {
+        let mut color = Color::new();
+        let mut throughput = Color::from(1.0, 1.0, 1.0);
+        let mut ray = *r;
+
+        for _ in 0..self.max_depth {
+            let rec = match world.objects.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    color = color + throughput * world.escaped(&ray);
+                    break;
+                }
+            };
+
+            color = color + throughput * rec.mat.emitted(&ray, &rec);
+            match rec.mat.scatter(&ray, &rec) {
+                Some(srec) => {
+                    throughput = throughput * srec.attenuation;
+                    ray = srec.ray.inherit(&ray);
+                }
+                None => break,
+            }
+        }
+
+        color
+    }
+}
+
+/// Light reaching the first non-specular surface directly from the lights or the
+/// background. Specular surfaces are followed up to `max_depth` bounces
+pub struct DirectLighting {
+    pub max_depth: i32,
+}
+
+impl Integrator for DirectLighting {
+    fn li(&self, r: &Ray, world: &World) -> Color {
+        let mut color = Color::new();
+        let mut throughput = Color::from(1.0, 1.0, 1.0);
+        let mut ray = *r;
+
+        for _ in 0..self.max_depth {
+            let rec = match world.objects.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    color = color + throughput * world.escaped(&ray);
+                    break;
+                }
+            };
+
+            color = color + throughput * rec.mat.emitted(&ray, &rec);
+            let srec = match rec.mat.scatter(&ray, &rec) {
+                Some(srec) => srec,
+                None => break,
+            };
+
+            let pdf = match srec.pdf {
+                Some(pdf) => pdf,
+                None => {
+                    throughput = throughput * srec.attenuation;
+                    ray = srec.ray.inherit(&ray);
+                    continue;
+                }
+            };
+
+            // Combine a light sample with the light found by the scattered ray
+            color = color + throughput * sample_lights(&ray, &rec, world);
+            let scattered = srec.ray.inherit(&ray);
+            let found = match world.objects.hit(&scattered, 0.001, f64::INFINITY) {
+                Some(light_rec) => {
+                    let light_pdf = world.lights.pdf_value(rec.p, scattered.dir);
+                    light_rec.mat.emitted(&scattered, &light_rec) * power_heuristic(pdf, light_pdf)
+                }
+                None => world.escaped(&scattered),
+            };
+            color = color + throughput * srec.attenuation * found;
+            break;
+        }
+
+        color
+    }
+}
+
+/// Fraction of the cosine-weighted hemisphere above the first hit that is unoccluded
+/// within `distance`
+pub struct AmbientOcclusion {
+    pub distance: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn li(&self, r: &Ray, world: &World) -> Color {
+        let rec = match world.objects.hit(r, 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => return Color::from(1.0, 1.0, 1.0),
+        };
+
+        let mut direction = rec.normal + Vec3::random_unit_vector();
+        if direction.near_zero() {
+            direction = rec.normal;
+        }
+        let probe = Ray::from(rec.p, vec3::unit_vector(direction), r.t);
+        match world.objects.hit(&probe, 0.001, self.distance) {
+            Some(_) => Color::new(),
+            None => Color::from(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+/// Shading normal of the first hit, mapped from [-1, 1] to [0, 1]
+pub struct Normals {}
+
+impl Integrator for Normals {
+    fn li(&self, r: &Ray, world: &World) -> Color {
+        match world.objects.hit(r, 0.001, f64::INFINITY) {
+            Some(rec) => debug_color(0.5 * (rec.normal + Color::from(1.0, 1.0, 1.0))),
+            None => Color::new(),
+        }
+    }
+}
+
+/// Distance to the first hit, from white up close to black at `max_distance`
+pub struct Depth {
+    pub max_distance: f64,
+}
+
+impl Integrator for Depth {
+    fn li(&self, r: &Ray, world: &World) -> Color {
+        match world.objects.hit(r, 0.001, f64::INFINITY) {
+            Some(rec) => {
+                let distance = rec.t * r.dir.length();
+                let shade = (1.0 - distance / self.max_distance).clamp(0.0, 1.0);
+                debug_color(Color::from(shade, shade, shade))
+            }
+            None => Color::new(),
+        }
+    }
+}
+
+/// Surface coordinates of the first hit as red and green
+pub struct Uv {}
+
+impl Integrator for Uv {
+    fn li(&self, r: &Ray, world: &World) -> Color {
+        match world.objects.hit(r, 0.001, f64::INFINITY) {
+            Some(rec) => debug_color(Color::from(rec.u, rec.v, 0.0)),
+            None => Color::new(),
+        }
+    }
+}
+
+/// A distinct flat color for every material instance
+pub struct MaterialId {
+    ids: HashMap<usize, usize>, // Position in the scene of each material, by address
+}
+
+impl MaterialId {
+    /// Numbers the materials in order, so that the colors are the same on every run
+    /// wherever the materials end up in memory
+    pub fn from(materials: &[Arc<dyn Material>]) -> Self {
+        let mut ids = HashMap::new();
+        for mat in materials {
+            let next = ids.len();
+            ids.entry(address(mat)).or_insert(next);
+        }
+        MaterialId { ids }
+    }
+}
+
+fn address(mat: &Arc<dyn Material>) -> usize {
+    Arc::as_ptr(mat) as *const () as usize
+}
+
+impl Integrator for MaterialId {
+    fn li(&self, r: &Ray, world: &World) -> Color {
+        match world.objects.hit(r, 0.001, f64::INFINITY) {
+            Some(rec) => {
+                let mut hasher = DefaultHasher::new();
+                self.ids.get(&address(&rec.mat)).hash(&mut hasher);
+                let hash = hasher.finish();
+                let channel = |shift: u32| ((hash >> shift) & 0xff) as f64 / 255.0;
+                debug_color(Color::from(channel(0), channel(8), channel(16)))
+            }
+            None => Color::new(),
+        }
+    }
+}
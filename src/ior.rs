@@ -0,0 +1,159 @@
+use crate::vec3::Color;
+
+/// Index of refraction of a dielectric, possibly varying with the wavelength
+#[derive(Debug, Copy, Clone)]
+pub enum Ior {
+    Constant(f64),
+    /// n = a + b / λ², with λ in micrometres
+    #[allow(dead_code)]
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// n² = 1 + Σ b λ² / (λ² - c), with λ in micrometres
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl Ior {
+    /// Borosilicate crown glass, the common optical glass
+    #[allow(dead_code)]
+    pub fn bk7() -> Self {
+        Ior::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+
+    /// Dense flint glass, with strong dispersion
+    #[allow(dead_code)]
+    pub fn sf11() -> Self {
+        Ior::Sellmeier {
+            b: [1.73759695, 0.313747346, 1.89878101],
+            c: [0.013188707, 0.0623068142, 155.23629],
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn diamond() -> Self {
+        Ior::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.030625, 0.011236, 0.0],
+        }
+    }
+
+    /// Index of refraction at `lambda` nanometres
+    pub fn at(&self, lambda: f64) -> f64 {
+        let l2 = (lambda / 1000.0) * (lambda / 1000.0);
+        match *self {
+            Ior::Constant(n) => n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f64 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+/// Complex index of refraction of a conductor, given for the red, green and blue
+/// wavelengths of `spectrum::RGB_WAVELENGTHS`
+#[derive(Debug, Copy, Clone)]
+pub struct ComplexIor {
+    pub eta: Color,
+    pub k: Color, // Extinction coefficient
+}
+
+impl ComplexIor {
+    #[allow(dead_code)]
+    pub fn from(eta: Color, k: Color) -> Self {
+        ComplexIor { eta, k }
+    }
+
+    #[allow(dead_code)]
+    pub fn gold() -> Self {
+        Self::from(
+            Color::from(0.143, 0.374, 1.442),
+            Color::from(3.983, 2.385, 1.603),
+        )
+    }
+
+    #[allow(dead_code)]
+    pub fn copper() -> Self {
+        Self::from(
+            Color::from(0.200, 0.924, 1.102),
+            Color::from(3.912, 2.452, 2.142),
+        )
+    }
+
+    #[allow(dead_code)]
+    pub fn aluminium() -> Self {
+        Self::from(
+            Color::from(1.657, 0.880, 0.521),
+            Color::from(9.224, 6.270, 4.837),
+        )
+    }
+
+    #[allow(dead_code)]
+    pub fn silver() -> Self {
+        Self::from(
+            Color::from(0.155, 0.117, 0.138),
+            Color::from(4.828, 3.122, 2.147),
+        )
+    }
+}
+
+/// Fraction of unpolarized light reflected by a conductor with index `eta` and
+/// extinction `k`, arriving at `cos_theta` to the normal
+pub fn conductor_fresnel(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos2.sqrt() * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conductor_fresnel_is_one_at_grazing() {
+        for ior in [ComplexIor::gold(), ComplexIor::aluminium()] {
+            for (eta, k) in [
+                (ior.eta.x, ior.k.x),
+                (ior.eta.y, ior.k.y),
+                (ior.eta.z, ior.k.z),
+            ] {
+                assert!((conductor_fresnel(0.0, eta, k) - 1.0).abs() < 1e-12);
+                assert!(conductor_fresnel(1e-4, eta, k) > 0.99);
+                assert!(conductor_fresnel(1.0, eta, k) < 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_fresnel_without_extinction_is_dielectric() {
+        // At normal incidence both polarizations reflect ((eta - 1) / (eta + 1))^2
+        for eta in [1.33_f64, 1.5, 2.4] {
+            let r0 = ((eta - 1.0) / (eta + 1.0)).powi(2);
+            assert!((conductor_fresnel(1.0, eta, 0.0) - r0).abs() < 1e-12);
+        }
+    }
+}
@@ -1,41 +1,76 @@
 use rand::prelude::*;
-use std::fs::File;
 use std::io;
-use std::io::prelude::*;
-use std::io::BufWriter;
-use std::rc::Rc;
+use std::sync::Arc;
 
+use crate::aabb::Aabb;
+use crate::aabox::Aabox;
 use crate::aarect::{Xyrect, Xzrect, Yzrect};
+use crate::bvh::BvhNode;
 use crate::camera::Camera;
-use crate::color::write_color;
+use crate::constant_medium::ConstantMedium;
+use crate::density::{Density, NoiseDensity, VoxelGrid};
+use crate::film::Film;
+use crate::heterogeneous_medium::HeterogeneousMedium;
 use crate::hittable::Hittable;
-use crate::hittable_list::HittableList;
-use crate::material::{Dielectric, DiffuseLight, Lambertian, Metal};
+use crate::image::Wrap;
+use crate::instance::{RotateY, Translate};
+use crate::integrator::World;
+use crate::ior::{ComplexIor, Ior};
+use crate::material::{
+    Coated, Conductor, Dielectric, DiffuseLight, Lambertian, Material, Metal, NormalMapped,
+    Principled, RoughDielectric,
+};
 use crate::moving_sphere::MovingSphere;
-use crate::ray::Ray;
+use crate::options::Options;
+use crate::phase::DoubleHenyeyGreenstein;
+use crate::render::Renderer;
+use crate::scene::{Scene, SceneKind};
 use crate::sphere::Sphere;
+use crate::texture::{Checker, ImageTexture, NoisePattern, NoiseTexture, Texture};
 use crate::vec3::{Color, Point, Vec3};
 
 mod aabb;
+mod aabox;
 mod aarect;
+mod bdpt;
 mod bvh;
 mod camera;
 mod color;
+mod constant_medium;
+mod density;
+mod film;
+mod heterogeneous_medium;
 mod hittable;
 mod hittable_list;
+mod image;
+mod instance;
+mod integrator;
+mod ior;
 mod material;
+mod microfacet;
 mod moving_sphere;
+mod onb;
+mod options;
+mod perlin;
+mod phase;
+mod photon;
 mod ray;
+mod render;
+mod scene;
+mod scheduler;
+mod spectrum;
 mod sphere;
+mod texture;
+mod tile;
 mod vec3;
 
-#[allow(dead_code)]
-fn random_scene() -> HittableList {
-    let mut objects = HittableList::new();
+fn random_scene() -> Scene {
+    let mut scene = Scene::new();
     let mut rng = thread_rng();
 
-    let ground_material = Rc::new(Lambertian::from(Color::from(0.8, 0.8, 0.0)));
-    objects.push(Rc::new(Sphere::from(
+    let checker = Checker::from_colors(Color::from(0.2, 0.3, 0.1), Color::from(0.9, 0.9, 0.9), 0.5);
+    let ground_material = Arc::new(Lambertian::from_texture(Arc::new(checker)));
+    scene.push(Arc::new(Sphere::from(
         Point::from(0.0, -1000.0, 0.0),
         1000.0,
         ground_material,
@@ -54,9 +89,9 @@ fn random_scene() -> HittableList {
                 if choose_mat < 0.8 {
                     // Diffuse
                     let albedo = Color::random() * Color::random();
-                    let sphere_material = Rc::new(Lambertian::from(albedo));
+                    let sphere_material = Arc::new(Lambertian::from(albedo));
                     let center2 = center + Vec3::from(0.0, rng.gen_range(0.0..0.5), 0.0);
-                    objects.push(Rc::new(MovingSphere::from(
+                    scene.push(Arc::new(MovingSphere::from(
                         center,
                         center2,
                         0.0,
@@ -68,62 +103,92 @@ fn random_scene() -> HittableList {
                     // Metal
                     let albedo = Color::random_range(0.5..1.0);
                     let fuzz = rng.gen_range(0.0..0.5);
-                    let sphere_material = Rc::new(Metal::from(albedo, fuzz));
-                    objects.push(Rc::new(Sphere::from(center, 0.2, sphere_material)));
+                    let sphere_material = Arc::new(Metal::from(albedo, fuzz));
+                    scene.push(Arc::new(Sphere::from(center, 0.2, sphere_material)));
                 } else {
                     // Glass
-                    let sphere_material = Rc::new(Dielectric::from(1.5));
-                    objects.push(Rc::new(Sphere::from(center, 0.2, sphere_material)));
+                    let sphere_material = Arc::new(Dielectric::from(1.5));
+                    scene.push(Arc::new(Sphere::from(center, 0.2, sphere_material)));
                 }
             }
         }
     }
 
-    let material1 = Rc::new(Dielectric::from(1.5));
-    objects.push(Rc::new(Sphere::from(
+    let material1 = Arc::new(Dielectric::from(1.5));
+    scene.push(Arc::new(Sphere::from(
         Point::from(0.0, 1.0, 0.0),
         1.0,
         material1,
     )));
 
-    let material2 = Rc::new(Lambertian::from(Color::from(0.4, 0.2, 0.1)));
-    objects.push(Rc::new(Sphere::from(
+    let material2 = Arc::new(Lambertian::from(Color::from(0.4, 0.2, 0.1)));
+    scene.push(Arc::new(Sphere::from(
         Point::from(-4.0, 1.0, 0.0),
         1.0,
         material2,
     )));
 
-    let material3 = Rc::new(Metal::from(Color::from(0.7, 0.6, 0.5), 0.0));
-    objects.push(Rc::new(Sphere::from(
+    let material3 = Arc::new(Metal::from(Color::from(0.7, 0.6, 0.5), 0.0));
+    scene.push(Arc::new(Sphere::from(
         Point::from(4.0, 1.0, 0.0),
         1.0,
         material3,
     )));
 
-    objects
+    scene
 }
 
-#[allow(dead_code)]
-fn simple_light_scene() -> HittableList {
-    let mut objects = HittableList::new();
+fn simple_light_scene() -> Scene {
+    let mut scene = Scene::new();
 
-    let ground_material = Rc::new(Lambertian::from(Color::from(0.8, 0.8, 0.0)));
-    objects.push(Rc::new(Sphere::from(
+    let marble = NoiseTexture::from(
+        NoisePattern::Marble,
+        4.0,
+        Color::new(),
+        Color::from(1.0, 1.0, 1.0),
+    );
+    let ground_material = Arc::new(Lambertian::from_texture(Arc::new(marble)));
+    scene.push(Arc::new(Sphere::from(
         Point::from(0.0, -1000.0, 0.0),
         1000.0,
         ground_material,
     )));
 
-    let sphere_material = Rc::new(Metal::from(Color::from(0.8, 0.8, 0.8), 0.8));
-    objects.push(Rc::new(Sphere::from(
+    // Metal polished in places
+    let roughness = NoiseTexture::from(
+        NoisePattern::Turbulence,
+        1.5,
+        Color::new(),
+        Color::from(1.0, 1.0, 1.0),
+    );
+    let sphere_material = Arc::new(Metal::from_texture(
+        Color::from(0.8, 0.8, 0.8),
+        Arc::new(roughness),
+    ));
+    scene.push(Arc::new(Sphere::from(
         Point::from(0.0, 2.0, 0.0),
         2.0,
         sphere_material,
     )));
 
+    // Wood with its darker rings carved in
+    let wood = Arc::new(NoiseTexture::from(
+        NoisePattern::Wood,
+        1.0,
+        Color::from(0.45, 0.25, 0.1),
+        Color::from(0.75, 0.5, 0.25),
+    ));
+    let carved =
+        NormalMapped::from_bump_map(Arc::new(Lambertian::from_texture(wood.clone())), wood, 0.4);
+    scene.push(Arc::new(Sphere::from(
+        Point::from(0.0, 1.0, 4.0),
+        1.0,
+        Arc::new(carved),
+    )));
+
     // Note that the light is brighter than (1, 1, 1)
-    let light_material = Rc::new(DiffuseLight::from(Color::from(4.0, 4.0, 4.0)));
-    objects.push(Rc::new(Xyrect::from(
+    let light_material = Arc::new(DiffuseLight::from(Color::from(4.0, 4.0, 4.0)));
+    scene.push_light(Arc::new(Xyrect::from(
         3.0,
         5.0,
         1.0,
@@ -132,25 +197,18 @@ fn simple_light_scene() -> HittableList {
         light_material,
     )));
 
-    objects
+    scene
 }
 
-#[allow(dead_code)]
-fn cornell_box_scene() -> HittableList {
-    let mut objects = HittableList::new();
+// Adds the walls of the Cornell box, leaving the light and the contents to the caller
+fn cornell_box_walls(scene: &mut Scene) {
+    let red = Arc::new(Lambertian::from(Color::from(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambertian::from(Color::from(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambertian::from(Color::from(0.12, 0.54, 0.15)));
 
-    // Cornell box
-    let red = Rc::new(Lambertian::from(Color::from(0.65, 0.05, 0.05)));
-    let white = Rc::new(Lambertian::from(Color::from(0.73, 0.73, 0.73)));
-    let green = Rc::new(Lambertian::from(Color::from(0.12, 0.54, 0.15)));
-    let light = Rc::new(DiffuseLight::from(Color::from(15.0, 15.0, 15.0)));
-
-    objects.push(Rc::new(Yzrect::from(0.0, 555.0, 0.0, 555.0, 555.0, green)));
-    objects.push(Rc::new(Yzrect::from(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    objects.push(Rc::new(Xzrect::from(
-        213.0, 343.0, 227.0, 332.0, 554.0, light,
-    )));
-    objects.push(Rc::new(Xzrect::from(
+    scene.push(Arc::new(Yzrect::from(0.0, 555.0, 0.0, 555.0, 555.0, green)));
+    scene.push(Arc::new(Yzrect::from(0.0, 555.0, 0.0, 555.0, 0.0, red)));
+    scene.push(Arc::new(Xzrect::from(
         0.0,
         555.0,
         0.0,
@@ -158,7 +216,7 @@ fn cornell_box_scene() -> HittableList {
         0.0,
         white.clone(),
     )));
-    objects.push(Rc::new(Xzrect::from(
+    scene.push(Arc::new(Xzrect::from(
         0.0,
         555.0,
         0.0,
@@ -166,63 +224,341 @@ fn cornell_box_scene() -> HittableList {
         555.0,
         white.clone(),
     )));
-    objects.push(Rc::new(Xyrect::from(0.0, 555.0, 0.0, 555.0, 555.0, white)));
+    scene.push(Arc::new(Xyrect::from(0.0, 555.0, 0.0, 555.0, 555.0, white)));
+}
+
+fn cornell_box_scene() -> Scene {
+    let mut scene = Scene::new();
+
+    // Cornell box
+    cornell_box_walls(&mut scene);
+    let light = Arc::new(DiffuseLight::from(Color::from(15.0, 15.0, 15.0)));
+    scene.push_light(Arc::new(Xzrect::from(
+        213.0, 343.0, 227.0, 332.0, 554.0, light,
+    )));
 
     // Objects
-    let metal = Rc::new(Metal::from(Color::from(0.8, 0.8, 0.8), 0.8));
-    objects.push(Rc::new(Sphere::from(
+    let metal = Arc::new(Metal::from(Color::from(0.8, 0.8, 0.8), 0.8));
+    scene.push(Arc::new(Sphere::from(
         Point::from(277.5, 100.0, 277.5),
         100.0,
         metal,
     )));
 
-    objects
+    scene
 }
 
-fn ray_color(r: &Ray, background: Color, objects: &mut dyn Hittable, depth: i32) -> Color {
-    // If we've exceeded the ray bounce limit, no more light is gathered
-    if depth <= 0 {
-        return Color::new();
-    }
+// Cornell box with a small bright light focused by a flint glass sphere onto the floor,
+// spreading the light into its colors
+fn caustics_scene() -> Scene {
+    let mut scene = Scene::new();
 
-    let record = objects.hit(r, 0.001, f64::INFINITY);
+    cornell_box_walls(&mut scene);
+    let light = Arc::new(DiffuseLight::from(Color::from(60.0, 60.0, 60.0)));
+    scene.push_light(Arc::new(Xzrect::from(
+        245.5, 310.5, 247.5, 312.5, 554.0, light,
+    )));
 
-    match record {
-        Some(rec) => match rec.mat.scatter(r, &rec) {
-            Some((attenuation, scattered)) => {
-                rec.mat.emitted()
-                    + attenuation * ray_color(&scattered, background, objects, depth - 1)
-            }
-            None => rec.mat.emitted(),
-        },
-        // If the ray hits nothing, return the background color
-        None => background,
+    let glass = Arc::new(Dielectric::from_ior(Ior::sf11()));
+    scene.push(Arc::new(Sphere::from(
+        Point::from(277.5, 180.0, 277.5),
+        90.0,
+        glass,
+    )));
+
+    scene
+}
+
+// Cornell box with a row of spheres made of `materials`
+fn sphere_row_scene(materials: Vec<Arc<dyn Material>>) -> Scene {
+    let mut scene = Scene::new();
+
+    cornell_box_walls(&mut scene);
+    let light = Arc::new(DiffuseLight::from(Color::from(15.0, 15.0, 15.0)));
+    scene.push_light(Arc::new(Xzrect::from(
+        213.0, 343.0, 227.0, 332.0, 554.0, light,
+    )));
+
+    let spacing = 555.0 / materials.len() as f64;
+    let radius = (0.5 * spacing - 10.0).min(80.0);
+    for (i, mat) in materials.into_iter().enumerate() {
+        scene.push(Arc::new(Sphere::from(
+            Point::from(555.0 - spacing * (i as f64 + 0.5), radius, 277.5),
+            radius,
+            mat,
+        )));
     }
+
+    scene
+}
+
+// Cornell box with a row of spheres showing off materials
+fn materials_scene() -> Scene {
+    // Wood under an amber varnish, and car paint with metallic flakes under a clear coat
+    let wood = Arc::new(NoiseTexture::from(
+        NoisePattern::Wood,
+        0.03,
+        Color::from(0.45, 0.25, 0.1),
+        Color::from(0.75, 0.5, 0.25),
+    ));
+    let varnished_wood = Coated::tinted(
+        Arc::new(Lambertian::from_texture(wood)),
+        1.5,
+        Color::from(0.95, 0.8, 0.6),
+    );
+    let paint = Principled::from(Color::from(0.6, 0.05, 0.05))
+        .with_metallic(0.5)
+        .with_roughness(0.4);
+    let car_paint = Coated::from(Arc::new(paint), 1.5);
+
+    sphere_row_scene(vec![
+        Arc::new(Conductor::from(ComplexIor::gold(), 0.3)),
+        Arc::new(Conductor::from(ComplexIor::copper(), 0.5)),
+        Arc::new(Conductor::from(ComplexIor::aluminium(), 0.15)),
+        Arc::new(RoughDielectric::from(1.5, 0.3)),
+        Arc::new(varnished_wood),
+        Arc::new(car_paint),
+    ])
+}
+
+// Cornell box with a row of spheres made of the principled material: plastic, varnished
+// paint, velvet, brushed metal and frosted glass
+fn principled_scene() -> Scene {
+    sphere_row_scene(vec![
+        Arc::new(Principled::from(Color::from(0.8, 0.1, 0.1)).with_roughness(0.3)),
+        Arc::new(
+            Principled::from(Color::from(0.1, 0.2, 0.6))
+                .with_roughness(0.6)
+                .with_clearcoat(1.0),
+        ),
+        Arc::new(
+            Principled::from(Color::from(0.5, 0.1, 0.4))
+                .with_roughness(1.0)
+                .with_sheen(1.0),
+        ),
+        Arc::new(
+            Principled::from(Color::from(0.9, 0.6, 0.3))
+                .with_metallic(1.0)
+                .with_roughness(0.35),
+        ),
+        Arc::new(
+            Principled::from(Color::from(0.8, 1.0, 0.9))
+                .with_roughness(0.2)
+                .with_transmission(1.0),
+        ),
+    ])
+}
+
+// Cornell box with two boxes of smoke
+fn cornell_smoke_scene() -> Scene {
+    let mut scene = Scene::new();
+
+    cornell_box_walls(&mut scene);
+    let light = Arc::new(DiffuseLight::from(Color::from(7.0, 7.0, 7.0)));
+    scene.push_light(Arc::new(Xzrect::from(
+        113.0, 443.0, 127.0, 432.0, 554.0, light,
+    )));
+
+    let white = Arc::new(Lambertian::from(Color::from(0.73, 0.73, 0.73)));
+    let box1 = Arc::new(Aabox::from(
+        Point::from(0.0, 0.0, 0.0),
+        Point::from(165.0, 330.0, 165.0),
+        white.clone(),
+    ));
+    let box1 = Arc::new(RotateY::from(box1, 15.0));
+    let box1 = Arc::new(Translate::from(box1, Vec3::from(265.0, 0.0, 295.0)));
+
+    let box2 = Arc::new(Aabox::from(
+        Point::from(0.0, 0.0, 0.0),
+        Point::from(165.0, 165.0, 165.0),
+        white,
+    ));
+    let box2 = Arc::new(RotateY::from(box2, -18.0));
+    let box2 = Arc::new(Translate::from(box2, Vec3::from(130.0, 0.0, 65.0)));
+
+    scene.push(Arc::new(ConstantMedium::from(box1, 0.01, Color::new())));
+    scene.push(Arc::new(ConstantMedium::from(
+        box2,
+        0.01,
+        Color::from(1.0, 1.0, 1.0),
+    )));
+
+    scene
+}
+
+// Globe and poster showing an image texture, or a checker when no image is given. The
+// globe may have a normal map too
+fn earth_scene(options: &Options) -> io::Result<Scene> {
+    let mut scene = Scene::new();
+
+    // The image is loaded once and shared by both objects
+    let image = match &options.texture {
+        Some(path) => Some(ImageTexture::load(path, Wrap::Repeat)?),
+        None => None,
+    };
+    let texture = |wrap: Wrap| -> Arc<dyn Texture> {
+        match &image {
+            Some(image) => Arc::new(image.with_wrap(wrap)),
+            None => Arc::new(Checker::from_colors(
+                Color::from(0.1, 0.2, 0.5),
+                Color::from(0.9, 0.9, 0.9),
+                0.5,
+            )),
+        }
+    };
+
+    let globe: Arc<dyn Material> = Arc::new(Lambertian::from_texture(texture(Wrap::Repeat)));
+    let globe = match &options.normal_map {
+        Some(path) => {
+            let normals = Arc::new(ImageTexture::load_linear(path, Wrap::Repeat)?);
+            Arc::new(NormalMapped::from_normal_map(globe, normals))
+        }
+        None => globe,
+    };
+    scene.push(Arc::new(Sphere::from(
+        Point::from(-2.3, 0.0, 0.0),
+        2.0,
+        globe,
+    )));
+
+    let poster = Arc::new(Lambertian::from_texture(texture(Wrap::Clamp)));
+    scene.push(Arc::new(Xyrect::from(0.3, 4.1, -0.95, 0.95, 0.0, poster)));
+
+    Ok(scene)
+}
+
+// Cornell box filled with a cloud, from a voxel grid file if one is given and from
+// noise otherwise
+fn volume_scene(options: &Options) -> io::Result<Scene> {
+    let mut scene = Scene::new();
+
+    cornell_box_walls(&mut scene);
+    let light = Arc::new(DiffuseLight::from(Color::from(15.0, 15.0, 15.0)));
+    scene.push_light(Arc::new(Xzrect::from(
+        213.0, 343.0, 227.0, 332.0, 554.0, light,
+    )));
+
+    let min = Point::from(90.0, 0.0, 90.0);
+    let max = Point::from(465.0, 400.0, 465.0);
+    let white = Arc::new(Lambertian::from(Color::from(0.73, 0.73, 0.73)));
+    let boundary = Arc::new(Aabox::from(min, max, white));
+
+    let bbox = Aabb::from(min, max);
+    let density: Arc<dyn Density> = match (&options.volume, options.volume_dims) {
+        (Some(path), Some(dims)) => Arc::new(VoxelGrid::load_raw(
+            path,
+            dims,
+            bbox,
+            options.volume_density,
+        )?),
+        (Some(path), None) => Arc::new(VoxelGrid::load_ascii(path, bbox, options.volume_density)?),
+        (None, _) => Arc::new(NoiseDensity::from(0.015, options.volume_density)),
+    };
+    // Clouds scatter mostly forwards, with some light scattered back
+    let phase = Arc::new(DoubleHenyeyGreenstein::from(0.7, -0.3, 0.8));
+    scene.push(Arc::new(HeterogeneousMedium::from_phase(
+        boundary,
+        density,
+        Color::from(0.9, 0.9, 0.9),
+        phase,
+    )));
+
+    Ok(scene)
 }
 
 fn main() -> io::Result<()> {
+    let options = Options::from_args()?;
+
     // Image
     let aspect_ratio = 1.0;
     let image_width = 600;
     let image_height = (image_width as f64 / aspect_ratio) as i32;
-    let samples_per_pixel = 200;
-    let max_depth = 50;
 
     // World
-    let mut objects = cornell_box_scene();
-    let background = Color::new();
+    let (scene, background, lookfrom, lookat, vfov, aperture) = match options.scene {
+        SceneKind::Random => (
+            random_scene(),
+            Color::from(0.70, 0.80, 1.00),
+            Point::from(13.0, 2.0, 3.0),
+            Point::from(0.0, 0.0, 0.0),
+            20.0,
+            0.1,
+        ),
+        SceneKind::SimpleLight => (
+            simple_light_scene(),
+            Color::new(),
+            Point::from(26.0, 3.0, 6.0),
+            Point::from(0.0, 2.0, 0.0),
+            20.0,
+            0.0,
+        ),
+        SceneKind::CornellBox => (
+            cornell_box_scene(),
+            Color::new(),
+            Point::from(278.0, 278.0, -800.0),
+            Point::from(278.0, 278.0, 0.0),
+            40.0,
+            0.0,
+        ),
+        SceneKind::CornellSmoke => (
+            cornell_smoke_scene(),
+            Color::new(),
+            Point::from(278.0, 278.0, -800.0),
+            Point::from(278.0, 278.0, 0.0),
+            40.0,
+            0.0,
+        ),
+        SceneKind::Volume => (
+            volume_scene(&options)?,
+            Color::new(),
+            Point::from(278.0, 278.0, -800.0),
+            Point::from(278.0, 278.0, 0.0),
+            40.0,
+            0.0,
+        ),
+        SceneKind::Earth => (
+            earth_scene(&options)?,
+            Color::from(0.70, 0.80, 1.00),
+            Point::from(0.0, 0.0, 12.0),
+            Point::from(0.0, 0.0, 0.0),
+            40.0,
+            0.0,
+        ),
+        SceneKind::Materials => (
+            materials_scene(),
+            Color::new(),
+            Point::from(278.0, 278.0, -800.0),
+            Point::from(278.0, 278.0, 0.0),
+            40.0,
+            0.0,
+        ),
+        SceneKind::Principled => (
+            principled_scene(),
+            Color::new(),
+            Point::from(278.0, 278.0, -800.0),
+            Point::from(278.0, 278.0, 0.0),
+            40.0,
+            0.0,
+        ),
+        SceneKind::Caustics => (
+            caustics_scene(),
+            Color::new(),
+            Point::from(278.0, 278.0, -800.0),
+            Point::from(278.0, 278.0, 0.0),
+            40.0,
+            0.0,
+        ),
+    };
+    let objects = BvhNode::from_list(&scene.objects, 0.0, 1.0);
 
-    let lookfrom = Point::from(278.0, 278.0, -800.0);
-    let lookat = Point::from(278.0, 278.0, 0.0);
     let vup = Vec3::from(0.0, 1.0, 0.0);
     let dist_to_focus = 10.0;
-    let aperture = 0.0;
 
     let camera = Camera::from(
         lookfrom,
         lookat,
         vup,
-        40.0,
+        vfov,
         aspect_ratio,
         aperture,
         dist_to_focus,
@@ -231,26 +567,70 @@ fn main() -> io::Result<()> {
     );
 
     // Render
-    let f = File::create("img.ppm")?;
-    let mut writer = BufWriter::new(f);
-
-    let header = format!("P3\n{image_width} {image_height}\n255\n");
-    writer.write_all(header.as_bytes())?;
-
-    for j in (0..image_height).rev() {
-        eprint!("\rScanlines remaining: {j:03}");
-        for i in 0..image_width {
-            let mut pixel_color = Color::new();
-            for _ in 0..samples_per_pixel {
-                let u = (i as f64 + random::<f64>()) / (image_width - 1) as f64;
-                let v = (j as f64 + random::<f64>()) / (image_height - 1) as f64;
-                let r = camera.get_ray(u, v);
-                pixel_color = pixel_color + ray_color(&r, background, &mut objects, max_depth);
-            }
-            write_color(&mut writer, pixel_color, samples_per_pixel)?;
+    // Distance based integrators are scaled to the farthest the scene reaches from the
+    // camera, bounded by the distance to its center plus half its diagonal
+    let scene_size = match objects.bounding_box(0.0, 1.0) {
+        Some(bbox) => {
+            let center = 0.5 * (bbox.min + bbox.max);
+            (center - lookfrom).length() + 0.5 * (bbox.max - bbox.min).length()
+        }
+        None => 1.0,
+    };
+    let world = World {
+        objects: &objects,
+        lights: &scene.lights,
+        background,
+    };
+    let integrator = integrator::create(&options, &world, scene_size, &scene.objects.materials());
+
+    let renderer = Renderer {
+        camera: &camera,
+        world,
+        integrator: integrator.as_ref(),
+        image_width,
+        image_height,
+        options: &options,
+    };
+
+    let mut film = match options.resume {
+        true => Film::load_checkpoint(&options.checkpoint)?,
+        false => {
+            let mut film = Film::from(image_width as usize, image_height as usize);
+            film.fingerprint = options.fingerprint();
+            film
         }
+    };
+    if film.fingerprint != options.fingerprint() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "checkpoint was rendered with another scene or other options".to_string(),
+        ));
+    }
+    if film.width != image_width as usize || film.height != image_height as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "checkpoint is {}x{} but the image is {image_width}x{image_height}",
+                film.width, film.height
+            ),
+        ));
+    }
+
+    let timings = renderer.render(&mut film)?;
+
+    scheduler::report_timings(&timings, 5);
+    if let Some(path) = &options.tile_stats {
+        scheduler::write_timings(path, &timings)?;
+    }
+
+    film.write_ppm(&options.output)?;
+    if let Some(path) = &options.heatmap {
+        film.write_heatmap(path, options.min_samples, options.samples)?;
+    }
+    if options.progressive {
+        film.save_checkpoint(&options.checkpoint)?;
     }
-    eprintln!("\nDone!");
+    eprintln!("Done!");
 
     Ok(())
 }
@@ -1,37 +1,99 @@
 use rand::prelude::*;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 use crate::hittable::HitRecord;
-use crate::ray::Ray;
+use crate::ior::{self, ComplexIor, Ior};
+use crate::microfacet::{Ggx, Gtr1};
+use crate::onb::Onb;
+use crate::phase::PhaseFunction;
+use crate::ray::{Differentials, Ray};
+use crate::spectrum;
+use crate::texture::{SolidColor, Texture};
 use crate::vec3::{self, Color, Vec3};
 
-pub trait Material {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+pub struct ScatterRecord {
+    pub attenuation: Color, // BSDF times cosine divided by the PDF of the sampled direction
+    pub ray: Ray,
+    pub pdf: Option<f64>, // Solid angle PDF of the sampled direction, None if specular
+}
+
+// Differentials of a ray leaving a specular hit, with `bend` turning each incoming
+// direction into the outgoing one. The surface is taken to be flat around the hit
+fn specular_differentials(
+    r: &Ray,
+    rec: &HitRecord,
+    bend: impl Fn(Vec3) -> Vec3,
+) -> Option<Differentials> {
+    let d = r.differentials?;
+    let (rx_orig, ry_orig) = d.at_plane(rec.p, rec.normal)?;
+    let rx_dir = bend(vec3::unit_vector(d.rx_dir));
+    let ry_dir = bend(vec3::unit_vector(d.ry_dir));
+    let finite = |v: Vec3| v.x.is_finite() && v.y.is_finite() && v.z.is_finite();
+    match finite(rx_dir) && finite(ry_dir) {
+        true => Some(Differentials {
+            rx_orig,
+            rx_dir,
+            ry_orig,
+            ry_dir,
+        }),
+        false => None,
+    }
+}
+
+pub trait Material: Send + Sync {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
+
+    /// BSDF times the cosine term for light leaving in direction `dir`. Specular
+    /// scattering can't be evaluated and isn't included
+    fn eval(&self, _r: &Ray, _rec: &HitRecord, _dir: Vec3) -> Color {
+        Color::new()
+    }
+
+    /// Solid angle PDF of `scatter` sampling direction `dir`, not counting specular
+    /// scattering
+    fn pdf(&self, _r: &Ray, _rec: &HitRecord, _dir: Vec3) -> f64 {
+        0.0
+    }
 
-    fn emitted(&self) -> Color {
+    /// Light emitted towards the origin of `r`
+    fn emitted(&self, _r: &Ray, _rec: &HitRecord) -> Color {
         Color::new()
     }
 }
 
 pub struct Lambertian {
-    pub albedo: Color,
+    pub albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     #[allow(dead_code)]
     pub fn new() -> Self {
         Lambertian {
-            albedo: Color::new(),
+            albedo: Arc::new(SolidColor::from(Color::new())),
         }
     }
 
     #[allow(dead_code)]
     pub fn from(albedo: Color) -> Self {
+        Lambertian {
+            albedo: Arc::new(SolidColor::from(albedo)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_texture(albedo: Arc<dyn Texture>) -> Self {
         Lambertian { albedo }
     }
+
+    fn albedo(&self, r: &Ray, rec: &HitRecord) -> Color {
+        let albedo = self.albedo.filtered(rec.u, rec.v, rec.p, rec.footprint(r));
+        spectrum::color_at(albedo, r)
+    }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
 
         // Catch degenerate scatter direction
@@ -39,13 +101,28 @@ impl Material for Lambertian {
             scatter_direction = rec.normal;
         }
 
-        Some((self.albedo, Ray::from(rec.p, scatter_direction, r.t)))
+        let scattered = Ray::from(rec.p, scatter_direction, r.t);
+        Some(ScatterRecord {
+            attenuation: self.albedo(r, rec),
+            pdf: Some(self.pdf(r, rec, scattered.dir)),
+            ray: scattered,
+        })
+    }
+
+    fn eval(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> Color {
+        self.albedo(r, rec) * self.pdf(r, rec, dir)
+    }
+
+    fn pdf(&self, _: &Ray, rec: &HitRecord, dir: Vec3) -> f64 {
+        // The directions are cosine distributed around the normal
+        let cosine = vec3::dot(&rec.normal, &vec3::unit_vector(dir));
+        cosine.max(0.0) / PI
     }
 }
 
 pub struct Metal {
     pub albedo: Color,
-    pub fuzz: f64,
+    pub fuzz: Arc<dyn Texture>, // Roughness, the mean of the color channels in [0, 1]
 }
 
 impl Metal {
@@ -53,52 +130,259 @@ impl Metal {
     pub fn new() -> Self {
         Metal {
             albedo: Color::new(),
-            fuzz: 1.0,
+            fuzz: Arc::new(SolidColor::from(Color::from(1.0, 1.0, 1.0))),
         }
     }
 
     #[allow(dead_code)]
     pub fn from(albedo: Color, fuzz: f64) -> Self {
+        let fuzz = fuzz.min(1.0);
         Metal {
             albedo,
-            fuzz: match fuzz < 1.0 {
-                true => fuzz,
-                false => 1.0,
-            },
+            fuzz: Arc::new(SolidColor::from(Color::from(fuzz, fuzz, fuzz))),
         }
     }
+
+    /// Metal with a roughness varying over the surface
+    #[allow(dead_code)]
+    pub fn from_texture(albedo: Color, fuzz: Arc<dyn Texture>) -> Self {
+        Metal { albedo, fuzz }
+    }
+
+    fn fuzz(&self, rec: &HitRecord) -> f64 {
+        let fuzz = self.fuzz.value(rec.u, rec.v, rec.p);
+        ((fuzz.x + fuzz.y + fuzz.z) / 3.0).clamp(0.0, 1.0)
+    }
 }
 
 impl Material for Metal {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let fuzz = self.fuzz(rec);
         let reflected = vec3::reflect(vec3::unit_vector(r.dir), rec.normal);
-        let scattered = Ray::from(
-            rec.p,
-            reflected + self.fuzz * Vec3::random_in_unit_sphere(),
-            r.t,
-        );
-        let attenuation = self.albedo;
+        let mut scattered = Ray::from(rec.p, reflected + fuzz * Vec3::random_in_unit_sphere(), r.t);
+        if vec3::dot(&scattered.dir, &rec.normal) <= 0.0 {
+            return None;
+        }
 
-        match vec3::dot(&scattered.dir, &rec.normal) > 0.0 {
-            true => Some((attenuation, scattered)),
-            false => None,
+        let pdf = match fuzz > 0.0 {
+            true => Some(self.pdf(r, rec, scattered.dir)),
+            false => {
+                let bend = |d: Vec3| vec3::reflect(d, rec.normal);
+                scattered.differentials = specular_differentials(r, rec, bend);
+                None
+            }
+        };
+        Some(ScatterRecord {
+            attenuation: spectrum::color_at(self.albedo, r),
+            ray: scattered,
+            pdf,
+        })
+    }
+
+    fn eval(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> Color {
+        // Directions below the surface are absorbed
+        match vec3::dot(&dir, &rec.normal) > 0.0 {
+            true => spectrum::color_at(self.albedo, r) * self.pdf(r, rec, dir),
+            false => Color::new(),
         }
     }
+
+    fn pdf(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> f64 {
+        let fuzz = self.fuzz(rec);
+        if fuzz <= 0.0 {
+            return 0.0;
+        }
+
+        // The unnormalized scatter direction is uniformly distributed in a ball of radius
+        // fuzz around the unit reflection vector. The density of a direction is the volume
+        // of the ball along that direction, weighted by the squared distance
+        let reflected = vec3::reflect(vec3::unit_vector(r.dir), rec.normal);
+        let b = vec3::dot(&vec3::unit_vector(dir), &reflected);
+        let discriminant = b * b - (1.0 - fuzz * fuzz);
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let t0 = (b - discriminant.sqrt()).max(0.0);
+        let t1 = (b + discriminant.sqrt()).max(0.0);
+        (t1.powi(3) - t0.powi(3)) / (4.0 * PI * fuzz.powi(3))
+    }
+}
+
+/// Metal made of GGX microfacets, reflecting with the Fresnel reflectance of its complex
+/// index of refraction
+pub struct Conductor {
+    pub ior: ComplexIor,
+    pub distribution: Ggx,
+}
+
+impl Conductor {
+    /// Conductor with a perceptual `roughness` in [0, 1], where 0 is a perfect mirror
+    #[allow(dead_code)]
+    pub fn from(ior: ComplexIor, roughness: f64) -> Self {
+        Conductor {
+            ior,
+            distribution: Ggx::from_roughness(roughness),
+        }
+    }
+
+    fn fresnel(&self, cos_theta: f64, r: &Ray) -> Color {
+        let eta = spectrum::color_at(self.ior.eta, r);
+        let k = spectrum::color_at(self.ior.k, r);
+        Color::from(
+            ior::conductor_fresnel(cos_theta, eta.x, k.x),
+            ior::conductor_fresnel(cos_theta, eta.y, k.y),
+            ior::conductor_fresnel(cos_theta, eta.z, k.z),
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let unit_direction = vec3::unit_vector(r.dir);
+
+        if self.distribution.is_smooth() {
+            let bend = |d: Vec3| vec3::reflect(d, rec.normal);
+            let mut scattered = Ray::from(rec.p, bend(unit_direction), r.t);
+            scattered.differentials = specular_differentials(r, rec, bend);
+            let cos_theta = vec3::dot(&-unit_direction, &rec.normal);
+            return Some(ScatterRecord {
+                attenuation: self.fresnel(cos_theta, r),
+                ray: scattered,
+                pdf: None,
+            });
+        }
+
+        let uvw = Onb::build_from_w(rec.normal);
+        let wo = uvw.to_local(-unit_direction);
+        let wm = self.distribution.sample_visible(wo);
+        let wi = vec3::reflect(-wo, wm);
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        let scattered = Ray::from(rec.p, uvw.local(wi), r.t);
+        let pdf = self.pdf(r, rec, scattered.dir);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: self.eval(r, rec, scattered.dir) / pdf,
+            ray: scattered,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn eval(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> Color {
+        if self.distribution.is_smooth() {
+            return Color::new();
+        }
+
+        let uvw = Onb::build_from_w(rec.normal);
+        let wo = uvw.to_local(-vec3::unit_vector(r.dir));
+        let wi = uvw.to_local(vec3::unit_vector(dir));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::new();
+        }
+
+        // The BSDF D F G / (4 cos_o cos_i), times cos_i
+        let wm = vec3::unit_vector(wo + wi);
+        let f = self.fresnel(vec3::dot(&wo, &wm), r);
+        let d = self.distribution.d(wm);
+        let g = self.distribution.g(wo, wi);
+        f * (d * g / (4.0 * wo.z))
+    }
+
+    fn pdf(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+
+        let uvw = Onb::build_from_w(rec.normal);
+        let wo = uvw.to_local(-vec3::unit_vector(r.dir));
+        let wi = uvw.to_local(vec3::unit_vector(dir));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+
+        // Reflecting about the microfacet normal squeezes its density by 4 (wo . wm)
+        let wm = vec3::unit_vector(wo + wi);
+        self.distribution.pdf_visible(wo, wm) / (4.0 * vec3::dot(&wo, &wm))
+    }
 }
 
 pub struct Dielectric {
-    pub ir: f64, // Index of Refraction
+    pub ior: Ior,          // Index of Refraction
+    pub absorption: Color, // Absorption coefficient per unit distance travelled inside
 }
 
 impl Dielectric {
     #[allow(dead_code)]
     pub fn new() -> Self {
-        Dielectric { ir: 1.5 } // Glass
+        Dielectric {
+            ior: Ior::Constant(1.5), // Glass
+            absorption: Color::new(),
+        }
     }
 
     #[allow(dead_code)]
     pub fn from(ir: f64) -> Self {
-        Dielectric { ir }
+        Dielectric {
+            ior: Ior::Constant(ir),
+            absorption: Color::new(),
+        }
+    }
+
+    /// Dielectric with an index of refraction that varies with the wavelength, splitting
+    /// light into its colors
+    #[allow(dead_code)]
+    pub fn from_ior(ior: Ior) -> Self {
+        Dielectric {
+            ior,
+            absorption: Color::new(),
+        }
+    }
+
+    /// Dielectric absorbing light travelling through it, like coloured glass or liquids
+    #[allow(dead_code)]
+    pub fn from_absorption(ior: Ior, absorption: Color) -> Self {
+        Dielectric { ior, absorption }
+    }
+
+    /// Dielectric letting through `color` of the light after travelling `distance` inside it
+    #[allow(dead_code)]
+    pub fn tinted(ior: Ior, color: Color, distance: f64) -> Self {
+        let absorption = |c: f64| -c.max(1e-6).ln() / distance;
+        Dielectric {
+            ior,
+            absorption: Color::from(
+                absorption(color.x),
+                absorption(color.y),
+                absorption(color.z),
+            ),
+        }
+    }
+
+    // Fraction of the light let through by the inside of the dielectric along `r` up to
+    // the hit, following the Beer-Lambert law
+    fn transmittance(&self, r: &Ray, rec: &HitRecord) -> Color {
+        // The ray travelled inside if it leaves through the hit
+        if rec.front_face || self.absorption.near_zero() {
+            return Color::from(1.0, 1.0, 1.0);
+        }
+
+        let distance = rec.t * r.dir.length();
+        let per_unit = Color::from(
+            (-self.absorption.x).exp(),
+            (-self.absorption.y).exp(),
+            (-self.absorption.z).exp(),
+        );
+        // Upsampling the transmittance over a unit distance keeps it between 0 and 1
+        let per_unit = spectrum::color_at(per_unit, r);
+        Color::from(
+            per_unit.x.max(0.0).powf(distance),
+            per_unit.y.max(0.0).powf(distance),
+            per_unit.z.max(0.0).powf(distance),
+        )
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -107,55 +391,1161 @@ impl Dielectric {
         let r0 = r0 * r0;
         r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
     }
+
+    // Fraction of the light arriving at `cos_theta` to the normal that is reflected,
+    // including total internal reflection
+    fn reflect_probability(cos_theta: f64, refraction_ratio: f64) -> f64 {
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        match refraction_ratio * sin_theta > 1.0 {
+            true => 1.0,
+            false => Self::reflectance(cos_theta, refraction_ratio),
+        }
+    }
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let attenuation = Color::from(1.0, 1.0, 1.0);
-        let refraction_ratio = match rec.front_face {
-            true => 1.0 / self.ir,
-            false => self.ir,
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let refraction_ratio = |ir: f64| match rec.front_face {
+            true => 1.0 / ir,
+            false => ir,
         };
 
         let unit_direction = vec3::unit_vector(r.dir);
         let cos_theta = vec3::dot(&-unit_direction, &rec.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
-        let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction =
-            if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > random() {
-                vec3::reflect(unit_direction, rec.normal)
-            } else {
-                vec3::refract(unit_direction, rec.normal, refraction_ratio)
+        // The wavelengths the channels stand for. Rays whose wavelengths were already
+        // reduced to a single one, or whose hero channel was already picked in RGB mode,
+        // refract as one
+        let lambda = match r.lambda {
+            Some(lambda) => [lambda.x, lambda.y, lambda.z],
+            None => spectrum::RGB_WAVELENGTHS,
+        };
+        let single = r.hero.is_some()
+            || (r.lambda.is_some() && lambda[0] == lambda[1] && lambda[1] == lambda[2]);
+
+        if !self.ior.is_dispersive() || single {
+            let ratio = refraction_ratio(self.ior.at(lambda[r.hero.unwrap_or(0)]));
+            let reflects = Self::reflect_probability(cos_theta, ratio) > random();
+            let bend = |d: Vec3| match reflects {
+                true => vec3::reflect(d, rec.normal),
+                false => vec3::refract(d, rec.normal, ratio),
             };
 
+            let mut scattered = Ray::from(rec.p, bend(unit_direction), r.t);
+            scattered.differentials = specular_differentials(r, rec, bend);
+            return Some(ScatterRecord {
+                attenuation: self.transmittance(r, rec),
+                ray: scattered,
+                pdf: None,
+            });
+        }
+
+        // Every wavelength reflects the same way, but refracts its own way. The direction
+        // is chosen for one hero channel, the first in spectral mode where the wavelengths
+        // are already random and any channel in RGB mode. The other channels keep their
+        // reflections, weighted by how likely they are compared to the hero's, while
+        // their refractions are dropped and made up for by weighting up the hero's
+        let hero = match r.lambda {
+            Some(_) => 0,
+            None => thread_rng().gen_range(0..3),
+        };
+        let ratios = lambda.map(|l| refraction_ratio(self.ior.at(l)));
+        let reflect = ratios.map(|ratio| Self::reflect_probability(cos_theta, ratio));
+        let reflects = reflect[hero] > random();
+        let bend = |d: Vec3| match reflects {
+            true => vec3::reflect(d, rec.normal),
+            false => vec3::refract(d, rec.normal, ratios[hero]),
+        };
+
+        let (attenuation, lambda, hero) = match reflects {
+            true => (
+                Color::from(reflect[0], reflect[1], reflect[2]) / reflect[hero],
+                None,
+                None,
+            ),
+            false => {
+                let mut attenuation = [0.0; 3];
+                attenuation[hero] = 3.0;
+                let attenuation = Color::from(attenuation[0], attenuation[1], attenuation[2]);
+                // Later refractions follow the hero wavelength alone, or in RGB mode the
+                // hero channel, the only one left
+                match r.lambda {
+                    Some(_) => (
+                        attenuation,
+                        Some(Vec3::from(lambda[hero], lambda[hero], lambda[hero])),
+                        None,
+                    ),
+                    None => (attenuation, None, Some(hero)),
+                }
+            }
+        };
+
+        let mut scattered = Ray::from(rec.p, bend(unit_direction), r.t);
+        scattered.lambda = lambda;
+        scattered.hero = hero;
+        scattered.differentials = specular_differentials(r, rec, bend);
+        Some(ScatterRecord {
+            attenuation: attenuation * self.transmittance(r, rec),
+            ray: scattered,
+            pdf: None,
+        })
+    }
+}
+
+/// Frosted glass made of GGX microfacets, each reflecting and refracting like a smooth
+/// `Dielectric` (Walter et al. 2007)
+pub struct RoughDielectric {
+    pub ior: Ior,          // Index of Refraction
+    pub absorption: Color, // Absorption coefficient per unit distance travelled inside
+    pub distribution: Ggx,
+}
+
+impl RoughDielectric {
+    /// Dielectric with a perceptual `roughness` in [0, 1], where 0 is perfectly smooth
+    #[allow(dead_code)]
+    pub fn from(ir: f64, roughness: f64) -> Self {
+        Self::from_ior(Ior::Constant(ir), roughness)
+    }
+
+    /// Dielectric with an index of refraction that varies with the wavelength
+    #[allow(dead_code)]
+    pub fn from_ior(ior: Ior, roughness: f64) -> Self {
+        Self::from_absorption(ior, Color::new(), roughness)
+    }
+
+    /// Dielectric absorbing light travelling through it
+    #[allow(dead_code)]
+    pub fn from_absorption(ior: Ior, absorption: Color, roughness: f64) -> Self {
+        RoughDielectric {
+            ior,
+            absorption,
+            distribution: Ggx::from_roughness(roughness),
+        }
+    }
+
+    // The same dielectric without the roughness
+    fn smooth(&self) -> Dielectric {
+        Dielectric::from_absorption(self.ior, self.absorption)
+    }
+
+    // Ratios of the indices of refraction on the side of the incoming ray and on the
+    // other, at the wavelength of each channel. Rays whose hero channel was already
+    // picked refract as that channel
+    fn refraction_ratios(&self, r: &Ray, rec: &HitRecord) -> [f64; 3] {
+        let lambda = match r.lambda {
+            Some(lambda) => [lambda.x, lambda.y, lambda.z],
+            None => spectrum::RGB_WAVELENGTHS,
+        };
+        let lambda = match r.hero {
+            Some(hero) => [lambda[hero]; 3],
+            None => lambda,
+        };
+        lambda.map(|l| match rec.front_face {
+            true => 1.0 / self.ior.at(l),
+            false => self.ior.at(l),
+        })
+    }
+
+    // `f` of the refraction ratio of each channel, evaluated once when they all match
+    fn per_channel(ratios: [f64; 3], f: impl Fn(f64) -> f64) -> [f64; 3] {
+        match ratios[0] == ratios[1] && ratios[1] == ratios[2] {
+            true => [f(ratios[0]); 3],
+            false => ratios.map(f),
+        }
+    }
+
+    // Directions of `r` and `dir` in a frame around the normal
+    fn local(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> (Vec3, Vec3) {
+        let uvw = Onb::build_from_w(rec.normal);
+        let wo = uvw.to_local(-vec3::unit_vector(r.dir));
+        let wi = uvw.to_local(vec3::unit_vector(dir));
+        (wo, wi)
+    }
+
+    // Microfacet normal refracting `wo` into `wi`, facing `wo`, or None if no microfacet
+    // facing both of them could
+    fn refracting_normal(wo: Vec3, wi: Vec3, ratio: f64) -> Option<Vec3> {
+        let wm = wo * ratio + wi;
+        if wm.near_zero() {
+            return None;
+        }
+        let wm = match wm.z < 0.0 {
+            true => -vec3::unit_vector(wm),
+            false => vec3::unit_vector(wm),
+        };
+        match vec3::dot(&wo, &wm) > 0.0 && vec3::dot(&wi, &wm) < 0.0 {
+            true => Some(wm),
+            false => None,
+        }
+    }
+
+    // The BSDF times cos_i for light of the given refraction ratio, in the local frame
+    fn eval_local(&self, wo: Vec3, wi: Vec3, ratio: f64) -> f64 {
+        // D F G / (4 cos_o cos_i), times cos_i
+        if wi.z > 0.0 {
+            let wm = vec3::unit_vector(wo + wi);
+            let f = Dielectric::reflect_probability(vec3::dot(&wo, &wm), ratio);
+            return self.distribution.d(wm) * self.distribution.g(wo, wi) * f / (4.0 * wo.z);
+        }
+
+        // The BTDF D (1 - F) G |wi . wm| |wo . wm| / (cos_o cos_i denom^2), times cos_i.
+        // Like `Dielectric`, radiance isn't scaled by the squared ratio of the indices
+        // as it crosses, so a smooth surface lets through all the light it doesn't reflect
+        let wm = match Self::refracting_normal(wo, wi, ratio) {
+            Some(wm) => wm,
+            None => return 0.0,
+        };
+        let (cos_o, cos_i) = (vec3::dot(&wo, &wm), vec3::dot(&wi, &wm));
+        let f = Dielectric::reflect_probability(cos_o, ratio);
+        let denom = cos_i + cos_o * ratio;
+        self.distribution.d(wm) * self.distribution.g(wo, wi) * (1.0 - f) * (cos_i * cos_o).abs()
+            / (wo.z * denom * denom)
+    }
+
+    // Density of sampling `wi` for light of the given refraction ratio, in the local frame
+    fn pdf_local(&self, wo: Vec3, wi: Vec3, ratio: f64) -> f64 {
+        // Reflecting about the microfacet normal squeezes its density by 4 (wo . wm),
+        // refracting through it by denom^2 / |wi . wm|
+        if wi.z > 0.0 {
+            let wm = vec3::unit_vector(wo + wi);
+            let cos_o = vec3::dot(&wo, &wm);
+            let f = Dielectric::reflect_probability(cos_o, ratio);
+            return self.distribution.pdf_visible(wo, wm) / (4.0 * cos_o) * f;
+        }
+
+        let wm = match Self::refracting_normal(wo, wi, ratio) {
+            Some(wm) => wm,
+            None => return 0.0,
+        };
+        let (cos_o, cos_i) = (vec3::dot(&wo, &wm), vec3::dot(&wi, &wm));
+        let f = Dielectric::reflect_probability(cos_o, ratio);
+        let denom = cos_i + cos_o * ratio;
+        self.distribution.pdf_visible(wo, wm) * cos_i.abs() / (denom * denom) * (1.0 - f)
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if self.distribution.is_smooth() {
+            return self.smooth().scatter(r, rec);
+        }
+
+        // Each channel refracts its own way, so the direction is sampled for a random
+        // one and weighed by the density of sampling it for any of them
+        let ratios = self.refraction_ratios(r, rec);
+        let ratio = match ratios[0] == ratios[1] && ratios[1] == ratios[2] {
+            true => ratios[0],
+            false => ratios[thread_rng().gen_range(0..3)],
+        };
+
+        let uvw = Onb::build_from_w(rec.normal);
+        let wo = uvw.to_local(-vec3::unit_vector(r.dir));
+        let wm = self.distribution.sample_visible(wo);
+        let reflect = Dielectric::reflect_probability(vec3::dot(&wo, &wm), ratio);
+        let reflects = reflect > random();
+        let wi = match reflects {
+            true => vec3::reflect(-wo, wm),
+            false => vec3::refract(-wo, wm, ratio),
+        };
+        // Reflections bent into the surface and refractions out of it have no path
+        if reflects != (wi.z > 0.0) {
+            return None;
+        }
+
+        let scattered = Ray::from(rec.p, uvw.local(wi), r.t);
+        let pdf = self.pdf(r, rec, scattered.dir);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: self.eval(r, rec, scattered.dir) / pdf,
+            ray: scattered,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn eval(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> Color {
+        if self.distribution.is_smooth() {
+            return Color::new();
+        }
+
+        let (wo, wi) = self.local(r, rec, dir);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return Color::new();
+        }
+
+        let ratios = self.refraction_ratios(r, rec);
+        let [x, y, z] = Self::per_channel(ratios, |ratio| self.eval_local(wo, wi, ratio));
+        // Light reaching the surface from inside was absorbed on the way
+        Color::from(x, y, z) * self.smooth().transmittance(r, rec)
+    }
+
+    fn pdf(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+
+        let (wo, wi) = self.local(r, rec, dir);
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+
+        let ratios = self.refraction_ratios(r, rec);
+        let pdfs = Self::per_channel(ratios, |ratio| self.pdf_local(wo, wi, ratio));
+        pdfs.iter().sum::<f64>() / 3.0
+    }
+}
+
+/// Principled BSDF after Burley's Disney BRDF, blending diffuse, specular, sheen,
+/// clearcoat and transmission lobes with intuitive parameters in [0, 1]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: f64, // Blends from a dielectric to a metal tinted by the base color
+    pub roughness: f64, // Perceptual roughness of the specular and transmission lobes
+    pub specular: f64, // Reflectance of dielectrics at normal incidence, 0.5 for 4%
+    pub sheen: f64,    // Grazing retroreflection, for cloth
+    pub clearcoat: f64, // Strength of a glossy varnish on top
+    pub clearcoat_gloss: f64, // Sharpness of the clearcoat's highlight
+    pub transmission: f64, // Blends from an opaque to a transparent dielectric
+}
+
+impl Principled {
+    // Lobes are sampled as rough surfaces, so smoother ones are made slightly rough
+    const MIN_ROUGHNESS: f64 = 0.05;
+    // Disney's model masks the clearcoat like a GGX surface of this width
+    const CLEARCOAT_MASKING: Ggx = Ggx { alpha: 0.25 };
+
+    #[allow(dead_code)]
+    pub fn from(base_color: Color) -> Self {
+        Self::from_texture(Arc::new(SolidColor::from(base_color)))
+    }
+
+    #[allow(dead_code)]
+    pub fn from_texture(base_color: Arc<dyn Texture>) -> Self {
+        Principled {
+            base_color,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            sheen: 0.0,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn with_metallic(mut self, metallic: f64) -> Self {
+        self.metallic = metallic.clamp(0.0, 1.0);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_roughness(mut self, roughness: f64) -> Self {
+        self.roughness = roughness.clamp(Self::MIN_ROUGHNESS, 1.0);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_specular(mut self, specular: f64) -> Self {
+        self.specular = specular.clamp(0.0, 1.0);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_sheen(mut self, sheen: f64) -> Self {
+        self.sheen = sheen.clamp(0.0, 1.0);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_clearcoat(mut self, clearcoat: f64) -> Self {
+        self.clearcoat = clearcoat.clamp(0.0, 1.0);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_clearcoat_gloss(mut self, clearcoat_gloss: f64) -> Self {
+        self.clearcoat_gloss = clearcoat_gloss.clamp(0.0, 1.0);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_transmission(mut self, transmission: f64) -> Self {
+        self.transmission = transmission.clamp(0.0, 1.0);
+        self
+    }
+
+    fn base_color(&self, r: &Ray, rec: &HitRecord) -> Color {
+        let color = self
+            .base_color
+            .filtered(rec.u, rec.v, rec.p, rec.footprint(r));
+        spectrum::color_at(color, r)
+    }
+
+    // Rough glass with the index of refraction giving the specular reflectance
+    fn dielectric(&self) -> RoughDielectric {
+        let r0 = (0.08 * self.specular).sqrt();
+        RoughDielectric::from((1.0 + r0) / (1.0 - r0), self.roughness)
+    }
+
+    // Weights of the diffuse (with sheen), specular, clearcoat and transmission lobes.
+    // The clearcoat is at most a quarter as strong as the others, as in Disney's model.
+    // Light only gets behind the surface by refracting through it, so back faces only
+    // have the transmission lobe
+    fn weights(&self, front_face: bool) -> [f64; 4] {
+        let dielectric = 1.0 - self.metallic;
+        let transmission = dielectric * self.transmission;
+        match front_face {
+            true => [
+                dielectric * (1.0 - self.transmission),
+                1.0 - transmission,
+                0.25 * self.clearcoat,
+                transmission,
+            ],
+            false => [0.0, 0.0, 0.0, transmission],
+        }
+    }
+
+    // Probabilities of sampling each lobe, all zero when there's nothing to sample
+    fn probabilities(&self, front_face: bool) -> [f64; 4] {
+        let weights = self.weights(front_face);
+        let total: f64 = weights.iter().sum();
+        match total > 0.0 {
+            true => weights.map(|w| w / total),
+            false => [0.0; 4],
+        }
+    }
+}
+
+// Schlick's Fresnel weight (1 - cos)^5
+fn schlick_weight(cosine: f64) -> f64 {
+    (1.0 - cosine).clamp(0.0, 1.0).powi(5)
+}
+
+impl Material for Principled {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let uvw = Onb::build_from_w(rec.normal);
+        let wo = uvw.to_local(-vec3::unit_vector(r.dir));
+        // Mirrors `wo` about a sampled microfacet normal
+        let reflect = |wm: Vec3| uvw.local(vec3::reflect(-wo, wm));
+
+        // Pick a lobe to sample, then weigh the direction by all of them. Rounding may
+        // leave the pick just past the last lobe
+        let probabilities = self.probabilities(rec.front_face);
+        let mut pick = random::<f64>();
+        let lobe = probabilities
+            .iter()
+            .position(|&p| {
+                pick -= p;
+                pick < 0.0
+            })
+            .or_else(|| probabilities.iter().rposition(|&p| p > 0.0))?;
+        let direction = match lobe {
+            0 => {
+                let direction = rec.normal + Vec3::random_unit_vector();
+                match direction.near_zero() {
+                    true => rec.normal,
+                    false => direction,
+                }
+            }
+            1 => reflect(Ggx::from_roughness(self.roughness).sample_visible(wo)),
+            2 => reflect(Gtr1::from_gloss(self.clearcoat_gloss).sample()),
+            _ => self.dielectric().scatter(r, rec)?.ray.dir,
+        };
+        // Reflections under the surface have no path, even where the transmission lobe
+        // could have sampled the direction
+        if lobe < 3 && vec3::dot(&direction, &rec.normal) <= 0.0 {
+            return None;
+        }
+
         let scattered = Ray::from(rec.p, direction, r.t);
-        Some((attenuation, scattered))
+        let pdf = self.pdf(r, rec, scattered.dir);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: self.eval(r, rec, scattered.dir) / pdf,
+            ray: scattered,
+            pdf: Some(pdf),
+        })
+    }
+
+    fn eval(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> Color {
+        let uvw = Onb::build_from_w(rec.normal);
+        let wo = uvw.to_local(-vec3::unit_vector(r.dir));
+        let wi = uvw.to_local(vec3::unit_vector(dir));
+        if wo.z <= 0.0 {
+            return Color::new();
+        }
+
+        let base = self.base_color(r, rec);
+        let [diffuse, specular, clearcoat, transmission] = self.weights(rec.front_face);
+        let mut value = Color::new();
+
+        if wi.z > 0.0 && rec.front_face {
+            let wm = vec3::unit_vector(wo + wi);
+            let cos_d = vec3::dot(&wi, &wm);
+            let fresnel = schlick_weight(cos_d);
+
+            // Lambertian with a retroreflective rise at grazing angles on rough surfaces,
+            // plus the sheen, times cos_i
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let retro = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z))
+                * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z));
+            let sheen = self.sheen * fresnel;
+            value = value + diffuse * (retro / PI * base + Color::from(sheen, sheen, sheen)) * wi.z;
+
+            // GGX reflection D F G / (4 cos_o cos_i), times cos_i, with the reflectance
+            // at normal incidence going from the specular one to the base color
+            let r0 = 0.08 * self.specular * (1.0 - self.metallic);
+            let f0 = Color::from(r0, r0, r0) + self.metallic * base;
+            let f = f0 + (Color::from(1.0, 1.0, 1.0) - f0) * fresnel;
+            let distribution = Ggx::from_roughness(self.roughness);
+            let glossy = distribution.d(wm) * distribution.g(wo, wi) / (4.0 * wo.z);
+            value = value + specular * glossy * f;
+
+            // GTR1 reflection with a fixed index of refraction of 1.5
+            let f = 0.04 + 0.96 * fresnel;
+            let d = Gtr1::from_gloss(self.clearcoat_gloss).d(wm);
+            let glossy = d * Self::CLEARCOAT_MASKING.g(wo, wi) / (4.0 * wo.z);
+            value = value + Color::from(1.0, 1.0, 1.0) * (clearcoat * glossy * f);
+        }
+
+        // Rough glass, with the light refracting through tinted by the base color. Light
+        // crosses a closed surface twice, so each crossing lets through its square root
+        if transmission > 0.0 {
+            let tint = match wi.z < 0.0 {
+                true => {
+                    let root = |c: f64| c.max(0.0).sqrt();
+                    Color::from(root(base.x), root(base.y), root(base.z))
+                }
+                false => Color::from(1.0, 1.0, 1.0),
+            };
+            value = value + transmission * tint * self.dielectric().eval(r, rec, dir);
+        }
+        value
+    }
+
+    fn pdf(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> f64 {
+        let uvw = Onb::build_from_w(rec.normal);
+        let wo = uvw.to_local(-vec3::unit_vector(r.dir));
+        let wi = uvw.to_local(vec3::unit_vector(dir));
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+
+        let [diffuse, specular, clearcoat, transmission] = self.probabilities(rec.front_face);
+        let mut pdf = 0.0;
+        if wi.z > 0.0 && rec.front_face {
+            // Reflecting about the microfacet normal squeezes its density by 4 (wo . wm)
+            let wm = vec3::unit_vector(wo + wi);
+            let jacobian = 4.0 * vec3::dot(&wo, &wm);
+            pdf += diffuse * wi.z / PI;
+            pdf += specular * Ggx::from_roughness(self.roughness).pdf_visible(wo, wm) / jacobian;
+            pdf += clearcoat * Gtr1::from_gloss(self.clearcoat_gloss).pdf(wm) / jacobian;
+        }
+        if transmission > 0.0 {
+            pdf += transmission * self.dielectric().pdf(r, rec, dir);
+        }
+        pdf
+    }
+}
+
+/// Material under a thin, smooth dielectric coat, like varnished wood or car paint.
+/// Light either reflects off the coat or refracts through it to scatter off the base
+/// and back out, losing what the coat absorbs on the way. Light reflected back down by
+/// the underside of the coat is lost, so coats darken the base somewhat more than real
+/// ones do (Weidlich and Wilkie 2007)
+pub struct Coated {
+    pub base: Arc<dyn Material>,
+    pub ir: f64,           // Index of Refraction of the coat
+    pub absorption: Color, // Optical depth of the coat, crossing it along the normal
+}
+
+impl Coated {
+    /// Clear coat
+    #[allow(dead_code)]
+    pub fn from(base: Arc<dyn Material>, ir: f64) -> Self {
+        Coated {
+            base,
+            ir,
+            absorption: Color::new(),
+        }
+    }
+
+    /// Coat letting through `color` of the light crossing it once along the normal
+    #[allow(dead_code)]
+    pub fn tinted(base: Arc<dyn Material>, ir: f64, color: Color) -> Self {
+        let absorption = |c: f64| -c.max(1e-6).ln();
+        Coated {
+            base,
+            ir,
+            absorption: Color::from(
+                absorption(color.x),
+                absorption(color.y),
+                absorption(color.z),
+            ),
+        }
+    }
+
+    // The ray `r` refracted into the coat, on its way to the base
+    fn inside(&self, r: &Ray, rec: &HitRecord) -> Ray {
+        let mut inside = *r;
+        inside.dir = vec3::refract(vec3::unit_vector(r.dir), rec.normal, 1.0 / self.ir);
+        inside
+    }
+
+    // Direction inside the coat that leaves it along `dir`
+    fn escaping(&self, rec: &HitRecord, dir: Vec3) -> Vec3 {
+        -vec3::refract(-vec3::unit_vector(dir), rec.normal, 1.0 / self.ir)
+    }
+
+    // Fraction of the light let through by the coat along paths making `cosines` with
+    // the normal inside it
+    fn transmittance(&self, r: &Ray, cosines: &[f64]) -> Color {
+        if self.absorption.near_zero() {
+            return Color::from(1.0, 1.0, 1.0);
+        }
+
+        let distance: f64 = cosines.iter().map(|cos| 1.0 / cos.max(1e-4)).sum();
+        let per_unit = Color::from(
+            (-self.absorption.x).exp(),
+            (-self.absorption.y).exp(),
+            (-self.absorption.z).exp(),
+        );
+        // Upsampling the transmittance across the coat keeps it between 0 and 1
+        let per_unit = spectrum::color_at(per_unit, r);
+        Color::from(
+            per_unit.x.max(0.0).powf(distance),
+            per_unit.y.max(0.0).powf(distance),
+            per_unit.z.max(0.0).powf(distance),
+        )
+    }
+
+    // Fraction of the light crossing the coat at `cos_theta` to the normal outside it
+    fn fresnel(&self, cos_theta: f64) -> f64 {
+        Dielectric::reflect_probability(cos_theta, 1.0 / self.ir)
+    }
+
+    // Factor turning the base's scattering towards `inner`, inside the coat, into
+    // scattering towards `dir` outside it. Refraction squeezes the solid angle by
+    // ir^2 cos_inner / cos_outer
+    fn squeeze(&self, rec: &HitRecord, dir: Vec3, inner: Vec3) -> f64 {
+        let cos_outer = vec3::dot(&vec3::unit_vector(dir), &rec.normal);
+        let cos_inner = vec3::dot(&inner, &rec.normal);
+        cos_outer / (self.ir * self.ir * cos_inner)
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let unit_direction = vec3::unit_vector(r.dir);
+        let cos_o = vec3::dot(&-unit_direction, &rec.normal).min(1.0);
+        let reflect = self.fresnel(cos_o);
+
+        if reflect > random() {
+            let bend = |d: Vec3| vec3::reflect(d, rec.normal);
+            let mut scattered = Ray::from(rec.p, bend(unit_direction), r.t);
+            scattered.differentials = specular_differentials(r, rec, bend);
+            return Some(ScatterRecord {
+                attenuation: Color::from(1.0, 1.0, 1.0),
+                ray: scattered,
+                pdf: None,
+            });
+        }
+
+        // Scatter off the base and refract back out, unless the light goes through the
+        // base or is totally reflected at the underside of the coat
+        let inside = self.inside(r, rec);
+        let srec = self.base.scatter(&inside, rec)?;
+        let inner = vec3::unit_vector(srec.ray.dir);
+        let cos_inner = vec3::dot(&inner, &rec.normal);
+        if cos_inner <= 0.0 || self.ir * (1.0 - cos_inner * cos_inner).sqrt() >= 1.0 {
+            return None;
+        }
+        let dir = vec3::refract(inner, -rec.normal, self.ir);
+        let cos_i = vec3::dot(&dir, &rec.normal);
+
+        let cos_down = vec3::dot(&-inside.dir, &rec.normal);
+        let attenuation = srec.attenuation
+            * self.transmittance(r, &[cos_down, cos_inner])
+            * (1.0 - self.fresnel(cos_i));
+        let pdf = srec
+            .pdf
+            .map(|pdf| (1.0 - reflect) * pdf * self.squeeze(rec, dir, inner));
+        Some(ScatterRecord {
+            attenuation,
+            ray: Ray::from(rec.p, dir, r.t),
+            pdf,
+        })
+    }
+
+    fn eval(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> Color {
+        let cos_o = vec3::dot(&-vec3::unit_vector(r.dir), &rec.normal);
+        let cos_i = vec3::dot(&vec3::unit_vector(dir), &rec.normal);
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return Color::new();
+        }
+
+        // The radiance scaling by ir^2 going in cancels the one coming out
+        let inside = self.inside(r, rec);
+        let inner = self.escaping(rec, dir);
+        let cos_down = vec3::dot(&-inside.dir, &rec.normal);
+        let cos_inner = vec3::dot(&inner, &rec.normal);
+        let crossing = (1.0 - self.fresnel(cos_o)) * (1.0 - self.fresnel(cos_i));
+        self.base.eval(&inside, rec, inner)
+            * self.transmittance(r, &[cos_down, cos_inner])
+            * (crossing * self.squeeze(rec, dir, inner))
+    }
+
+    fn pdf(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> f64 {
+        let cos_o = vec3::dot(&-vec3::unit_vector(r.dir), &rec.normal);
+        let cos_i = vec3::dot(&vec3::unit_vector(dir), &rec.normal);
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return 0.0;
+        }
+
+        let inside = self.inside(r, rec);
+        let inner = self.escaping(rec, dir);
+        (1.0 - self.fresnel(cos_o))
+            * self.base.pdf(&inside, rec, inner)
+            * self.squeeze(rec, dir, inner)
+    }
+
+    fn emitted(&self, r: &Ray, rec: &HitRecord) -> Color {
+        let cos_o = vec3::dot(&-vec3::unit_vector(r.dir), &rec.normal);
+        let inside = self.inside(r, rec);
+        let cos_down = vec3::dot(&-inside.dir, &rec.normal);
+        self.base.emitted(&inside, rec)
+            * self.transmittance(r, &[cos_down])
+            * (1.0 - self.fresnel(cos_o))
+    }
+}
+
+/// Where the normals shading a surface come from
+pub enum NormalSource {
+    /// Tangent space normals encoded as colors, with x along dpdu, y along dpdv and z
+    /// out of the surface, each mapped from [-1, 1] to [0, 1]
+    NormalMap(Arc<dyn Texture>),
+    /// Heights along the normal, the mean of the color channels times a scale
+    BumpMap(Arc<dyn Texture>, f64),
+}
+
+/// Material shaded with normals perturbed by a texture, giving detail to flat surfaces.
+/// Integrators tracing light from the lights must evaluate it for light going the other
+/// way and use the geometric normal for their cosines, which makes up for the shading
+/// normal not being the real one
+pub struct NormalMapped {
+    pub material: Arc<dyn Material>,
+    pub source: NormalSource,
+}
+
+impl NormalMapped {
+    #[allow(dead_code)]
+    pub fn from_normal_map(material: Arc<dyn Material>, normals: Arc<dyn Texture>) -> Self {
+        NormalMapped {
+            material,
+            source: NormalSource::NormalMap(normals),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_bump_map(
+        material: Arc<dyn Material>,
+        heights: Arc<dyn Texture>,
+        scale: f64,
+    ) -> Self {
+        NormalMapped {
+            material,
+            source: NormalSource::BumpMap(heights, scale),
+        }
+    }
+
+    // Perturbed normal pointing out of the surface, or None where the surface has no
+    // tangents to orient the perturbation by
+    fn outward_normal(&self, r: &Ray, rec: &HitRecord) -> Option<Vec3> {
+        let n = match rec.front_face {
+            true => rec.normal,
+            false => -rec.normal,
+        };
+        if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            return None;
+        }
+
+        let normal = match &self.source {
+            NormalSource::NormalMap(normals) => {
+                let c = normals.filtered(rec.u, rec.v, rec.p, rec.footprint(r));
+                let t = vec3::unit_vector(rec.dpdu - vec3::dot(&rec.dpdu, &n) * n);
+                let b = rec.dpdv - vec3::dot(&rec.dpdv, &n) * n - vec3::dot(&rec.dpdv, &t) * t;
+                let b = vec3::unit_vector(b);
+                (2.0 * c.x - 1.0) * t + (2.0 * c.y - 1.0) * b + (2.0 * c.z - 1.0) * n
+            }
+            NormalSource::BumpMap(heights, scale) => {
+                // Slopes of the height by finite differences over about the footprint
+                let delta = (0.5 * rec.footprint(r)).max(1e-4);
+                let height = |u: f64, v: f64, p: Vec3| {
+                    let h = heights.value(u, v, p);
+                    scale * (h.x + h.y + h.z) / 3.0
+                };
+                let h = height(rec.u, rec.v, rec.p);
+                let dhdu = (height(rec.u + delta, rec.v, rec.p + delta * rec.dpdu) - h) / delta;
+                let dhdv = (height(rec.u, rec.v + delta, rec.p + delta * rec.dpdv) - h) / delta;
+                let dpdu = rec.dpdu + dhdu * n;
+                let dpdv = rec.dpdv + dhdv * n;
+                let normal = vec3::cross(&dpdu, &dpdv);
+                match vec3::dot(&normal, &n) < 0.0 {
+                    true => -normal,
+                    false => normal,
+                }
+            }
+        };
+        match normal.near_zero() {
+            true => None,
+            false => Some(vec3::unit_vector(normal)),
+        }
+    }
+
+    // Copy of the hit record with the perturbed normal. Normals turned away from the
+    // ray are left unperturbed, so the surface doesn't go black where they would be
+    fn shade(&self, r: &Ray, rec: &HitRecord) -> HitRecord {
+        let mut shaded = rec.clone();
+        if let Some(outward) = self.outward_normal(r, rec) {
+            let normal = match rec.front_face {
+                true => outward,
+                false => -outward,
+            };
+            if vec3::dot(&r.dir, &normal) < 0.0 {
+                shaded.normal = normal;
+            }
+        }
+        shaded
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.material.scatter(r, &self.shade(r, rec))
+    }
+
+    fn eval(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> Color {
+        self.material.eval(r, &self.shade(r, rec), dir)
+    }
+
+    fn pdf(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> f64 {
+        self.material.pdf(r, &self.shade(r, rec), dir)
+    }
+
+    fn emitted(&self, r: &Ray, rec: &HitRecord) -> Color {
+        self.material.emitted(r, rec)
+    }
+}
+
+/// Scattering inside participating media, distributed by a phase function
+pub struct Volumetric {
+    pub albedo: Color,
+    pub phase: Arc<dyn PhaseFunction>,
+}
+
+impl Volumetric {
+    #[allow(dead_code)]
+    pub fn from(albedo: Color, phase: Arc<dyn PhaseFunction>) -> Self {
+        Volumetric { albedo, phase }
+    }
+}
+
+impl Material for Volumetric {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let scattered = Ray::from(rec.p, self.phase.sample(r.dir), r.t);
+        // Phase functions are sampled exactly, so only the albedo is left
+        Some(ScatterRecord {
+            attenuation: spectrum::color_at(self.albedo, r),
+            pdf: Some(self.pdf(r, rec, scattered.dir)),
+            ray: scattered,
+        })
+    }
+
+    fn eval(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> Color {
+        // There's no cosine term inside a volume
+        spectrum::color_at(self.albedo, r) * self.pdf(r, rec, dir)
+    }
+
+    fn pdf(&self, r: &Ray, _: &HitRecord, dir: Vec3) -> f64 {
+        self.phase.eval(r.dir, dir)
     }
 }
 
 pub struct DiffuseLight {
-    pub emit: Color,
+    pub emit: Arc<dyn Texture>,
 }
 
 impl DiffuseLight {
     #[allow(dead_code)]
     pub fn new() -> Self {
-        DiffuseLight { emit: Color::new() }
+        DiffuseLight {
+            emit: Arc::new(SolidColor::from(Color::new())),
+        }
     }
 
     #[allow(dead_code)]
     pub fn from(emit: Color) -> Self {
+        DiffuseLight {
+            emit: Arc::new(SolidColor::from(emit)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_texture(emit: Arc<dyn Texture>) -> Self {
         DiffuseLight { emit }
     }
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, _: &Ray, _: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, _: &Ray, _: &HitRecord) -> Option<ScatterRecord> {
         None
     }
 
-    fn emitted(&self) -> Color {
-        self.emit
+    fn emitted(&self, r: &Ray, rec: &HitRecord) -> Color {
+        let emit = self.emit.filtered(rec.u, rec.v, rec.p, rec.footprint(r));
+        spectrum::color_at(emit, r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Point;
+
+    // Integrates `f` over the sphere of directions around the z axis with the midpoint rule
+    fn integrate_sphere(f: impl Fn(Vec3) -> Color) -> Color {
+        let (n_theta, n_phi) = (1000, 200);
+        let (d_theta, d_phi) = (PI / n_theta as f64, 2.0 * PI / n_phi as f64);
+        let mut sum = Color::new();
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let w = Vec3::from(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                sum = sum + f(w) * (theta.sin() * d_theta * d_phi);
+            }
+        }
+        sum
+    }
+
+    // A ray along `dir` hitting the plane z = 0 at the origin after travelling 2 units
+    fn hit_plane(mat: Arc<dyn Material>, dir: Vec3) -> (Ray, HitRecord) {
+        let dir = vec3::unit_vector(dir);
+        let r = Ray::from(Point::new() - 2.0 * dir, dir, 0.0);
+        let normal = Vec3::from(0.0, 0.0, 1.0);
+        let rec = HitRecord::from(&r, Point::new(), 2.0, 0.0, 0.0, normal, mat);
+        (r, rec)
+    }
+
+    // Mean attenuation of `n` scattered rays, which estimates the integral of `eval`
+    fn mean_attenuation(r: &Ray, rec: &HitRecord, n: usize) -> Color {
+        let sum = (0..n)
+            .filter_map(|_| rec.mat.scatter(r, rec))
+            .fold(Color::new(), |sum, srec| sum + srec.attenuation);
+        sum / n as f64
+    }
+
+    #[test]
+    fn dielectric_reflectance_matches_schlick_at_normal_incidence() {
+        for ior in [1.33_f64, 1.5, 2.4] {
+            let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+            for ratio in [ior, 1.0 / ior] {
+                assert!((Dielectric::reflectance(1.0, ratio) - r0).abs() < 1e-12);
+                assert!((Dielectric::reflect_probability(1.0, ratio) - r0).abs() < 1e-12);
+            }
+            // Where Schlick's approximation is exact
+            assert!((ior::conductor_fresnel(1.0, ior, 0.0) - r0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn dielectric_reflects_everything_past_the_critical_angle() {
+        let ratio: f64 = 1.5;
+        let cos_critical = (1.0 - 1.0 / (ratio * ratio)).sqrt();
+        assert_eq!(
+            Dielectric::reflect_probability(cos_critical - 1e-3, ratio),
+            1.0
+        );
+        assert!(Dielectric::reflect_probability(cos_critical + 1e-3, ratio) < 1.0);
+    }
+
+    #[test]
+    fn rough_dielectric_refracts_each_wavelength_its_own_way() {
+        let mat = Arc::new(RoughDielectric::from_ior(Ior::sf11(), 0.1));
+        let (mut r, rec) = hit_plane(mat.clone(), Vec3::from(0.0, 0.8, -0.6));
+        for (channel, lambda) in spectrum::RGB_WAVELENGTHS.into_iter().enumerate() {
+            let ratio = 1.0 / Ior::sf11().at(lambda);
+            let dir = vec3::refract(vec3::unit_vector(r.dir), rec.normal, ratio);
+            let f = mat.eval(&r, &rec, dir);
+            let f = [f.x, f.y, f.z];
+            let brightest = (0..3).max_by(|&a, &b| f[a].total_cmp(&f[b])).unwrap();
+            assert_eq!(brightest, channel, "{f:?}");
+        }
+
+        // Once the hero channel is picked, every channel refracts as it does
+        r.hero = Some(2);
+        let ratio = 1.0 / Ior::sf11().at(spectrum::RGB_WAVELENGTHS[2]);
+        let dir = vec3::refract(vec3::unit_vector(r.dir), rec.normal, ratio);
+        let f = mat.eval(&r, &rec, dir);
+        assert!(f.x == f.y && f.y == f.z && f.x > 0.0);
+    }
+
+    #[test]
+    fn rough_dielectric_scattering_matches_eval() {
+        let mat = Arc::new(RoughDielectric::from_ior(Ior::sf11(), 0.5));
+        for dir in [Vec3::from(0.0, 0.8, -0.6), Vec3::from(0.3, 0.0, 0.5)] {
+            let (r, rec) = hit_plane(mat.clone(), dir);
+            let frame = Onb::build_from_w(rec.normal);
+            let expected = integrate_sphere(|w| mat.eval(&r, &rec, frame.local(w)));
+            let total = integrate_sphere(|w| {
+                let pdf = mat.pdf(&r, &rec, frame.local(w));
+                Color::from(pdf, pdf, pdf)
+            });
+            assert!(total.x > 0.5 && total.x < 1.01, "{total:?}");
+
+            let mean = mean_attenuation(&r, &rec, 200_000);
+            assert!(
+                (mean - expected).length() < 0.02,
+                "{mean:?} against {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_absorbs_light_travelling_inside() {
+        let clear = Arc::new(RoughDielectric::from(1.5, 0.5));
+        let absorption = Color::from(0.1, 0.3, 0.0);
+        let tinted = Arc::new(RoughDielectric::from_absorption(
+            Ior::Constant(1.5),
+            absorption,
+            0.5,
+        ));
+
+        // Leaving through the surface after 2 units inside
+        let (r, rec) = hit_plane(clear.clone(), Vec3::from(0.0, 0.3, 1.0));
+        let (_, tinted_rec) = hit_plane(tinted.clone(), Vec3::from(0.0, 0.3, 1.0));
+        assert!(!rec.front_face);
+        let dir = Vec3::from(0.1, 0.2, -1.0);
+        let f = clear.eval(&r, &rec, dir);
+        let g = tinted.eval(&r, &tinted_rec, dir);
+        assert!(f.x > 0.0);
+        assert!((g.x / f.x - (-0.2_f64).exp()).abs() < 1e-9);
+        assert!((g.y / f.y - (-0.6_f64).exp()).abs() < 1e-9);
+        assert!((g.z / f.z - 1.0).abs() < 1e-9);
+    }
+
+    fn principled_materials() -> Vec<(&'static str, Arc<Principled>)> {
+        // Glosses sharp enough for the clearcoat to slip between the directions that
+        // `integrate_sphere` samples are left out
+        let base = || Principled::from(Color::from(0.8, 0.4, 0.2));
+        vec![
+            ("plastic", base().with_roughness(0.3)),
+            ("cloth", base().with_roughness(0.9).with_sheen(1.0)),
+            ("metal", base().with_metallic(1.0).with_roughness(0.4)),
+            (
+                "varnish",
+                base().with_clearcoat(1.0).with_clearcoat_gloss(0.5),
+            ),
+            ("glass", base().with_roughness(0.3).with_transmission(1.0)),
+            (
+                "mixed",
+                base()
+                    .with_transmission(0.5)
+                    .with_clearcoat(0.5)
+                    .with_clearcoat_gloss(0.5),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, mat)| (name, Arc::new(mat)))
+        .collect()
+    }
+
+    #[test]
+    fn principled_pdf_integrates_to_one() {
+        for (name, mat) in principled_materials() {
+            // Seen from outside and, through the transmission, from inside
+            for dir in [
+                Vec3::from(0.0, 0.0, -1.0),
+                Vec3::from(0.0, 0.6, -0.8),
+                Vec3::from(0.0, 0.3, 1.0),
+            ] {
+                let (r, rec) = hit_plane(mat.clone(), dir);
+                if !rec.front_face && mat.transmission == 0.0 {
+                    continue;
+                }
+                let frame = Onb::build_from_w(rec.normal);
+                let total = integrate_sphere(|w| {
+                    let pdf = mat.pdf(&r, &rec, frame.local(w));
+                    Color::from(pdf, pdf, pdf)
+                });
+
+                // Glossy lobes lose the few directions they reflect under the surface,
+                // which `scatter` drops
+                let n = 100_000;
+                let scattered = (0..n).filter(|_| mat.scatter(&r, &rec).is_some()).count();
+                let fraction = scattered as f64 / n as f64;
+                assert!((total.x - fraction).abs() < 0.005, "{name}: {}", total.x);
+            }
+        }
+    }
+
+    #[test]
+    fn principled_scattering_matches_eval() {
+        for (name, mat) in principled_materials() {
+            for dir in [Vec3::from(0.0, 0.6, -0.8), Vec3::from(0.0, 0.3, 1.0)] {
+                let (r, rec) = hit_plane(mat.clone(), dir);
+                for _ in 0..1000 {
+                    let srec = match mat.scatter(&r, &rec) {
+                        Some(srec) => srec,
+                        None => continue,
+                    };
+                    let pdf = mat.pdf(&r, &rec, srec.ray.dir);
+                    let weight = mat.eval(&r, &rec, srec.ray.dir) / pdf;
+                    assert_eq!(srec.pdf, Some(pdf), "{name}");
+                    assert!((srec.attenuation - weight).length() < 1e-9, "{name}");
+                }
+
+                let frame = Onb::build_from_w(rec.normal);
+                let expected = integrate_sphere(|w| mat.eval(&r, &rec, frame.local(w)));
+                let mean = mean_attenuation(&r, &rec, 100_000);
+                assert!(
+                    (mean - expected).length() < 0.02,
+                    "{name}: {mean:?} against {expected:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn principled_back_faces_only_transmit() {
+        let dir = Vec3::from(0.0, 0.3, 1.0);
+        let opaque = Arc::new(Principled::from(Color::from(0.8, 0.4, 0.2)).with_clearcoat(1.0));
+        let (r, rec) = hit_plane(opaque.clone(), dir);
+        assert!(!rec.front_face);
+        assert!(opaque.scatter(&r, &rec).is_none());
+        let reflected = vec3::reflect(r.dir, rec.normal);
+        assert!(opaque.eval(&r, &rec, reflected).near_zero());
+        assert_eq!(opaque.pdf(&r, &rec, reflected), 0.0);
+
+        // Inside glass, the pdf is the rough dielectric's alone
+        let glass = Arc::new(
+            Principled::from(Color::from(0.8, 0.4, 0.2))
+                .with_transmission(0.5)
+                .with_clearcoat(1.0),
+        );
+        let (r, rec) = hit_plane(glass.clone(), dir);
+        for dir in [reflected, Vec3::from(0.1, 0.2, 1.0)] {
+            let pdf = glass.dielectric().pdf(&r, &rec, dir);
+            assert!(pdf > 0.0);
+            assert!((glass.pdf(&r, &rec, dir) - pdf).abs() < 1e-12);
+        }
     }
 }
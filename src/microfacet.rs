@@ -0,0 +1,223 @@
+use rand::prelude::*;
+use std::f64::consts::PI;
+
+use crate::vec3::{self, Vec3};
+
+/// GGX (Trowbridge-Reitz) distribution of microfacet normals, with Smith masking and
+/// shadowing. Directions are in a local frame with the surface normal along z
+pub struct Ggx {
+    pub alpha: f64, // Width of the distribution, zero for a perfectly smooth surface
+}
+
+impl Ggx {
+    /// Distribution for a perceptual `roughness` in [0, 1], squared to get the width
+    pub fn from_roughness(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Ggx {
+            alpha: roughness * roughness,
+        }
+    }
+
+    /// Whether the surface is smooth enough to be treated as a perfect mirror
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < 1e-3
+    }
+
+    /// Density of microfacet normals `wm` per unit projected area
+    pub fn d(&self, wm: Vec3) -> f64 {
+        let cos2 = wm.z * wm.z;
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let tan2 = (1.0 - cos2) / cos2;
+        let e = 1.0 + tan2 / a2;
+        1.0 / (PI * a2 * cos2 * cos2 * e * e)
+    }
+
+    // Smith's auxiliary function, the masked area over the visible area
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2) / cos2;
+        0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0)
+    }
+
+    /// Fraction of the microfacets visible from `w`
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of the microfacets visible from both `wo` and `wi`
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of `sample_visible` picking the microfacet normal `wm` seen from `wo`
+    pub fn pdf_visible(&self, wo: Vec3, wm: Vec3) -> f64 {
+        // Normals facing away from `wo` are hidden from it
+        let cos = vec3::dot(&wo, &wm);
+        if wo.z == 0.0 || cos <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) / wo.z.abs() * self.d(wm) * cos
+    }
+
+    /// Samples a microfacet normal among those visible from `wo`, following Heitz's
+    /// method of sampling the projected hemisphere of the stretched distribution
+    pub fn sample_visible(&self, wo: Vec3) -> Vec3 {
+        // Flip `wo` above the surface, and the normal back with it
+        let flip = wo.z < 0.0;
+        let wo = match flip {
+            true => -wo,
+            false => wo,
+        };
+
+        let vh = vec3::unit_vector(Vec3::from(self.alpha * wo.x, self.alpha * wo.y, wo.z));
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = match len2 > 0.0 {
+            true => Vec3::from(-vh.y, vh.x, 0.0) / len2.sqrt(),
+            false => Vec3::from(1.0, 0.0, 0.0),
+        };
+        let t2 = vec3::cross(&vh, &t1);
+
+        // Point on the disk, squeezed onto the part of it visible from `wo`
+        let r = random::<f64>().sqrt();
+        let phi = 2.0 * PI * random::<f64>();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+        let wm = vec3::unit_vector(Vec3::from(
+            self.alpha * nh.x,
+            self.alpha * nh.y,
+            nh.z.max(1e-6),
+        ));
+        match flip {
+            true => -wm,
+            false => wm,
+        }
+    }
+}
+
+/// Generalized Trowbridge-Reitz distribution with an exponent of 1 (Burley 2012), whose
+/// long tails give the haze around the highlight of a clear coat. Normals are sampled
+/// from the whole distribution rather than the part visible from the viewer
+pub struct Gtr1 {
+    pub alpha: f64,
+}
+
+impl Gtr1 {
+    /// Distribution for a `gloss` in [0, 1], narrowing from a width of 0.1 to 0.001
+    pub fn from_gloss(gloss: f64) -> Self {
+        let gloss = gloss.clamp(0.0, 1.0);
+        Gtr1 {
+            alpha: 0.1 * (1.0 - gloss) + 0.001 * gloss,
+        }
+    }
+
+    /// Density of microfacet normals `wm` per unit projected area
+    pub fn d(&self, wm: Vec3) -> f64 {
+        if wm.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * wm.z * wm.z))
+    }
+
+    /// Density of `sample` picking the microfacet normal `wm`
+    pub fn pdf(&self, wm: Vec3) -> f64 {
+        self.d(wm) * wm.z.max(0.0)
+    }
+
+    /// Samples a microfacet normal by its projected area
+    pub fn sample(&self) -> Vec3 {
+        // Inverting the CDF of the squared cosine
+        let a2 = self.alpha * self.alpha;
+        let cos2 = (1.0 - a2.powf(1.0 - random::<f64>())) / (1.0 - a2);
+        let sin = (1.0 - cos2).max(0.0).sqrt();
+        let phi = 2.0 * PI * random::<f64>();
+        Vec3::from(sin * phi.cos(), sin * phi.sin(), cos2.sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Integrates `f` over the hemisphere above the surface with the midpoint rule
+    fn integrate_hemisphere(f: impl Fn(Vec3) -> f64) -> f64 {
+        let (n_theta, n_phi) = (2000, 200);
+        let (d_theta, d_phi) = (0.5 * PI / n_theta as f64, 2.0 * PI / n_phi as f64);
+        let mut sum = 0.0;
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let w = Vec3::from(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                );
+                sum += f(w) * theta.sin() * d_theta * d_phi;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn pdf_visible_integrates_to_one() {
+        for alpha in [0.1, 0.4, 0.9] {
+            let ggx = Ggx { alpha };
+            for theta in [0.0_f64, 0.6, 1.4] {
+                let wo = Vec3::from(theta.sin(), 0.0, theta.cos());
+                let total = integrate_hemisphere(|wm| ggx.pdf_visible(wo, wm));
+                assert!(
+                    (total - 1.0).abs() < 1e-2,
+                    "alpha {alpha}, theta {theta}: {total}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sampled_normals_face_the_viewer() {
+        let ggx = Ggx { alpha: 0.5 };
+        let wo = vec3::unit_vector(Vec3::from(0.8, 0.3, 0.2));
+        for _ in 0..1000 {
+            let wm = ggx.sample_visible(wo);
+            assert!(wm.z > 0.0 && vec3::dot(&wo, &wm) > 0.0);
+        }
+    }
+
+    #[test]
+    fn gtr1_pdf_integrates_to_one() {
+        for gloss in [0.0, 0.5, 0.9] {
+            let gtr1 = Gtr1::from_gloss(gloss);
+            let total = integrate_hemisphere(|wm| gtr1.pdf(wm));
+            assert!((total - 1.0).abs() < 1e-2, "gloss {gloss}: {total}");
+        }
+    }
+
+    #[test]
+    fn sampled_gtr1_normals_follow_the_pdf() {
+        let gtr1 = Gtr1::from_gloss(0.5);
+        // Fraction of the normals within `theta` of the surface normal
+        let n = 100_000;
+        for theta in [0.02_f64, 0.1, 0.5] {
+            let inside = (0..n).filter(|_| gtr1.sample().z > theta.cos()).count();
+            let expected = integrate_hemisphere(|wm| match wm.z > theta.cos() {
+                true => gtr1.pdf(wm),
+                false => 0.0,
+            });
+            let fraction = inside as f64 / n as f64;
+            assert!(
+                (fraction - expected).abs() < 0.01,
+                "theta {theta}: {fraction}"
+            );
+        }
+    }
+}
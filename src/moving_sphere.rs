@@ -1,9 +1,10 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::aabb::{surrounding_box, Aabb};
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::sphere::{sphere_tangents, sphere_uv};
 use crate::vec3::{self, Point};
 
 pub struct MovingSphere {
@@ -12,7 +13,7 @@ pub struct MovingSphere {
     time0: f64,
     time1: f64,
     radius: f64,
-    mat: Rc<dyn Material>,
+    mat: Arc<dyn Material>,
 }
 
 impl MovingSphere {
@@ -23,7 +24,7 @@ impl MovingSphere {
         time0: f64,
         time1: f64,
         radius: f64,
-        mat: Rc<dyn Material>,
+        mat: Arc<dyn Material>,
     ) -> Self {
         MovingSphere {
             center0,
@@ -67,7 +68,13 @@ impl Hittable for MovingSphere {
         let p = r.at(t);
         let outward_normal = (p - self.center(r.t)) / self.radius;
 
-        Some(HitRecord::from(r, p, t, outward_normal, self.mat.clone()))
+        let (u, v) = sphere_uv(outward_normal);
+
+        let (dpdu, dpdv) = sphere_tangents(outward_normal, self.radius);
+        Some(
+            HitRecord::from(r, p, t, u, v, outward_normal, self.mat.clone())
+                .with_tangents(dpdu, dpdv),
+        )
     }
 
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
@@ -81,4 +88,8 @@ impl Hittable for MovingSphere {
         );
         Some(surrounding_box(box0, box1))
     }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![self.mat.clone()]
+    }
 }
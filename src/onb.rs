@@ -0,0 +1,36 @@
+use crate::vec3::{self, Vec3};
+
+/// Orthonormal basis
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// Basis with `w` along `n`
+    pub fn build_from_w(n: Vec3) -> Self {
+        let w = vec3::unit_vector(n);
+        let a = match w.x.abs() > 0.9 {
+            true => Vec3::from(0.0, 1.0, 0.0),
+            false => Vec3::from(1.0, 0.0, 0.0),
+        };
+        let v = vec3::unit_vector(vec3::cross(&w, &a));
+        let u = vec3::cross(&w, &v);
+        Onb { u, v, w }
+    }
+
+    /// Vector with coordinates `a` in the basis
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    /// Coordinates of `a` in the basis
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::from(
+            vec3::dot(&a, &self.u),
+            vec3::dot(&a, &self.v),
+            vec3::dot(&a, &self.w),
+        )
+    }
+}
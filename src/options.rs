@@ -0,0 +1,212 @@
+use std::env;
+use std::io;
+use std::str::FromStr;
+use std::thread;
+
+use crate::integrator::IntegratorKind;
+use crate::scene::SceneKind;
+use crate::tile::TileOrder;
+
+pub struct Options {
+    pub output: String,
+    pub scene: SceneKind,
+    pub samples: u32,
+    pub progressive: bool,
+    pub pass_samples: u32,
+    pub checkpoint: String,
+    pub checkpoint_interval: u64, // Seconds between checkpoints in progressive mode
+    pub resume: bool,
+    pub adaptive: bool,
+    pub min_samples: u32,
+    pub noise_threshold: f64,
+    pub heatmap: Option<String>, // Image of the samples taken per pixel
+    pub volume: Option<String>,  // Voxel grid for the volume scene
+    pub volume_dims: Option<[usize; 3]>, // Grid size of a raw voxel file
+    pub volume_density: f64,     // Scale of the voxel values, or the noise density
+    pub texture: Option<String>, // Image for the earth scene
+    pub normal_map: Option<String>, // Normal map for the globe of the earth scene
+    pub integrator: IntegratorKind,
+    pub spectral: bool, // Trace sampled wavelengths instead of RGB
+    pub max_depth: i32,
+    pub rr_depth: i32,  // Bounces before Russian roulette may terminate a path
+    pub photons: usize, // Photons emitted for the global photon map
+    pub caustic_photons: usize, // Photons emitted for the caustic photon map
+    pub gather_photons: usize, // Photons used per radiance estimate
+    pub threads: usize,
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    pub tile_stats: Option<String>, // CSV file for per-tile timings
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Options {
+            output: "img.ppm".to_string(),
+            scene: SceneKind::CornellBox,
+            samples: 200,
+            progressive: false,
+            pass_samples: 4,
+            checkpoint: "img.ckpt".to_string(),
+            checkpoint_interval: 60,
+            resume: false,
+            adaptive: false,
+            min_samples: 32,
+            noise_threshold: 0.005,
+            heatmap: None,
+            volume: None,
+            volume_dims: None,
+            volume_density: 0.05,
+            texture: None,
+            normal_map: None,
+            integrator: IntegratorKind::Path,
+            spectral: false,
+            max_depth: 50,
+            rr_depth: 3,
+            photons: 100_000,
+            caustic_photons: 1_000_000,
+            gather_photons: 100,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            tile_stats: None,
+        }
+    }
+
+    pub fn from_args() -> io::Result<Self> {
+        let mut options = Self::new();
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| invalid_input(format!("missing value for {arg}")))
+            };
+            match arg.as_str() {
+                "--output" => options.output = value()?,
+                "--scene" => options.scene = parse(&value()?)?,
+                "--samples" => options.samples = parse(&value()?)?,
+                "--progressive" => options.progressive = true,
+                "--pass-samples" => options.pass_samples = parse(&value()?)?,
+                "--checkpoint" => options.checkpoint = value()?,
+                "--checkpoint-interval" => options.checkpoint_interval = parse(&value()?)?,
+                "--resume" => options.resume = true,
+                "--adaptive" => options.adaptive = true,
+                "--min-samples" => options.min_samples = parse(&value()?)?,
+                "--noise-threshold" => options.noise_threshold = parse(&value()?)?,
+                "--heatmap" => options.heatmap = Some(value()?),
+                "--volume" => options.volume = Some(value()?),
+                "--volume-dims" => options.volume_dims = Some(parse_dims(&value()?)?),
+                "--volume-density" => options.volume_density = parse(&value()?)?,
+                "--texture" => options.texture = Some(value()?),
+                "--normal-map" => options.normal_map = Some(value()?),
+                "--integrator" => options.integrator = parse(&value()?)?,
+                "--spectral" => options.spectral = true,
+                "--max-depth" => options.max_depth = parse(&value()?)?,
+                "--rr-depth" => options.rr_depth = parse(&value()?)?,
+                "--photons" => options.photons = parse(&value()?)?,
+                "--caustic-photons" => options.caustic_photons = parse(&value()?)?,
+                "--gather-photons" => options.gather_photons = parse(&value()?)?,
+                "--threads" => options.threads = parse(&value()?)?,
+                "--tile-size" => options.tile_size = parse(&value()?)?,
+                "--tile-order" => options.tile_order = parse(&value()?)?,
+                "--tile-stats" => options.tile_stats = Some(value()?),
+                _ => return Err(invalid_input(format!("unknown argument {arg}"))),
+            }
+        }
+
+        if options.samples == 0
+            || options.threads == 0
+            || options.tile_size == 0
+            || options.pass_samples == 0
+            || options.gather_photons == 0
+        {
+            return Err(invalid_input(
+                "samples, thread count, tile size, pass samples and gather photons must be positive"
+                    .to_string(),
+            ));
+        }
+        if options.max_depth < 0 || options.rr_depth < 0 {
+            return Err(invalid_input(
+                "max depth and Russian roulette depth can't be negative".to_string(),
+            ));
+        }
+        if options.spectral && !options.integrator.supports_spectral() {
+            return Err(invalid_input(
+                "the integrator doesn't support spectral rendering".to_string(),
+            ));
+        }
+        // Resuming only makes sense when adding more progressive passes
+        if options.resume {
+            options.progressive = true;
+        }
+        Ok(options)
+    }
+
+    /// Hash of the options that change what the image converges to, stored in checkpoints
+    /// so that a render can only be resumed with the same scene. It's FNV-1a rather than
+    /// the standard library hasher, whose output may change between Rust releases
+    pub fn fingerprint(&self) -> u64 {
+        let description = format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {} {} {} {}",
+            self.scene,
+            self.integrator,
+            self.volume,
+            self.volume_dims,
+            self.volume_density,
+            self.texture,
+            self.normal_map,
+            self.spectral,
+            self.max_depth,
+            self.rr_depth,
+            self.photons,
+            self.caustic_photons,
+            self.gather_photons,
+        );
+        description.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid_input(format!("invalid value '{value}'")))
+}
+
+// Parses a grid size written as NXxNYxNZ
+fn parse_dims(value: &str) -> io::Result<[usize; 3]> {
+    let dims: Vec<usize> = value.split('x').map(parse).collect::<io::Result<_>>()?;
+    dims.try_into()
+        .map_err(|_| invalid_input(format!("invalid grid size '{value}'")))
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprint_tells_scenes_apart() {
+        let mut options = Options::new();
+        let cornell = options.fingerprint();
+        assert_eq!(cornell, Options::new().fingerprint());
+
+        // Options that only change how samples are taken keep the fingerprint
+        options.samples = 7;
+        options.threads = 3;
+        assert_eq!(options.fingerprint(), cornell);
+
+        options.scene = SceneKind::CornellSmoke;
+        assert_ne!(options.fingerprint(), cornell);
+    }
+}
@@ -0,0 +1,179 @@
+use rand::prelude::*;
+use std::f64::consts::PI;
+
+use crate::onb::Onb;
+use crate::vec3::{self, Vec3};
+
+/// Angular distribution of light scattered inside a medium. Directions are directions of
+/// travel, so `cos_theta` is 1 for light that keeps going straight
+pub trait PhaseFunction: Send + Sync {
+    /// Density of scattering into a direction at `cos_theta` to the incoming light,
+    /// which is also the PDF of `sample`
+    fn p(&self, cos_theta: f64) -> f64;
+
+    /// Samples the cosine between the incoming and the scattered direction
+    fn sample_cos_theta(&self) -> f64;
+
+    /// Samples a scattered direction for light travelling along `dir`
+    fn sample(&self, dir: Vec3) -> Vec3 {
+        let cos_theta = self.sample_cos_theta().clamp(-1.0, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * random::<f64>();
+        let uvw = Onb::build_from_w(dir);
+        uvw.local(Vec3::from(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+
+    /// `p` for light travelling along `dir` scattered into `scattered`
+    fn eval(&self, dir: Vec3, scattered: Vec3) -> f64 {
+        let cos_theta = vec3::dot(&vec3::unit_vector(dir), &vec3::unit_vector(scattered));
+        self.p(cos_theta)
+    }
+}
+
+/// Scatters equally in all directions
+pub struct Isotropic {}
+
+impl PhaseFunction for Isotropic {
+    fn p(&self, _: f64) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn sample_cos_theta(&self) -> f64 {
+        1.0 - 2.0 * random::<f64>()
+    }
+}
+
+/// Henyey-Greenstein phase function. Positive `g` scatters forward, negative `g`
+/// backward and zero is isotropic
+pub struct HenyeyGreenstein {
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    #[allow(dead_code)]
+    pub fn from(g: f64) -> Self {
+        HenyeyGreenstein {
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn p(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    fn sample_cos_theta(&self) -> f64 {
+        let g = self.g;
+        if g.abs() < 1.0e-3 {
+            return 1.0 - 2.0 * random::<f64>();
+        }
+        // Inverting the CDF of the cosine
+        let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * random::<f64>());
+        (1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)
+    }
+}
+
+/// Blend of two Henyey-Greenstein lobes, typically a strong forward lobe with a weaker
+/// backward one. `weight` is the share of the first lobe
+pub struct DoubleHenyeyGreenstein {
+    pub first: HenyeyGreenstein,
+    pub second: HenyeyGreenstein,
+    pub weight: f64,
+}
+
+impl DoubleHenyeyGreenstein {
+    #[allow(dead_code)]
+    pub fn from(g1: f64, g2: f64, weight: f64) -> Self {
+        DoubleHenyeyGreenstein {
+            first: HenyeyGreenstein::from(g1),
+            second: HenyeyGreenstein::from(g2),
+            weight: weight.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl PhaseFunction for DoubleHenyeyGreenstein {
+    fn p(&self, cos_theta: f64) -> f64 {
+        self.weight * self.first.p(cos_theta) + (1.0 - self.weight) * self.second.p(cos_theta)
+    }
+
+    fn sample_cos_theta(&self) -> f64 {
+        match random::<f64>() < self.weight {
+            true => self.first.sample_cos_theta(),
+            false => self.second.sample_cos_theta(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Integrates `f` of the cosine over the sphere of directions with the midpoint rule
+    fn integrate_sphere(f: impl Fn(f64) -> f64) -> f64 {
+        let steps = 100_000;
+        let step = 2.0 / steps as f64;
+        (0..steps)
+            .map(|i| 2.0 * PI * f(-1.0 + (i as f64 + 0.5) * step) * step)
+            .sum()
+    }
+
+    fn phase_functions() -> Vec<(Box<dyn PhaseFunction>, f64)> {
+        vec![
+            (Box::new(Isotropic {}), 0.0),
+            (Box::new(HenyeyGreenstein::from(0.0)), 0.0),
+            (Box::new(HenyeyGreenstein::from(0.3)), 0.3),
+            (Box::new(HenyeyGreenstein::from(-0.7)), -0.7),
+            (Box::new(HenyeyGreenstein::from(0.9)), 0.9),
+            (
+                Box::new(DoubleHenyeyGreenstein::from(0.8, -0.4, 0.75)),
+                0.75 * 0.8 - 0.25 * 0.4,
+            ),
+        ]
+    }
+
+    #[test]
+    fn phase_functions_integrate_to_one_with_mean_cosine_g() {
+        for (phase, g) in phase_functions() {
+            let total = integrate_sphere(|cos| phase.p(cos));
+            assert!((total - 1.0).abs() < 1e-3, "g {g}: {total}");
+            let mean_cos = integrate_sphere(|cos| cos * phase.p(cos));
+            assert!((mean_cos - g).abs() < 1e-3, "g {g}: {mean_cos}");
+        }
+    }
+
+    #[test]
+    fn sampled_cosines_follow_p() {
+        let n = 200_000;
+        let bins = 10;
+        for (phase, g) in phase_functions() {
+            let mut counts = vec![0; bins];
+            let mut sum = 0.0;
+            for _ in 0..n {
+                let cos = phase.sample_cos_theta();
+                assert!((-1.0..=1.0).contains(&cos));
+                counts[(((cos + 1.0) / 2.0 * bins as f64) as usize).min(bins - 1)] += 1;
+                sum += cos;
+            }
+            assert!((sum / n as f64 - g).abs() < 0.01, "g {g}");
+
+            // Fraction of the samples in each bin against the integral of p over it
+            for (i, &count) in counts.iter().enumerate() {
+                let (lo, width) = (-1.0 + 2.0 * i as f64 / bins as f64, 2.0 / bins as f64);
+                let expected = integrate_sphere(|cos| match cos >= lo && cos < lo + width {
+                    true => phase.p(cos),
+                    false => 0.0,
+                });
+                let fraction = count as f64 / n as f64;
+                assert!((fraction - expected).abs() < 0.005, "g {g}, bin {i}");
+            }
+        }
+    }
+}
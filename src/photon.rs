@@ -0,0 +1,454 @@
+use rand::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f64::consts::PI;
+use std::thread;
+
+use crate::hittable::{self, HitRecord, Hittable};
+use crate::integrator::{self, Integrator, World};
+use crate::ray::Ray;
+use crate::vec3::{self, Color, Point, Vec3};
+
+/// A packet of light power arriving at a non-specular surface
+#[derive(Copy, Clone)]
+pub struct Photon {
+    pub p: Point,
+    pub dir: Vec3, // Direction of travel
+    pub power: Color,
+}
+
+// A photon found by a nearest neighbour search, ordered by distance so that the heap
+// keeps the farthest on top
+struct Neighbour {
+    distance_squared: f64,
+    index: usize,
+}
+
+impl PartialEq for Neighbour {
+    fn eq(&self, other: &Self) -> bool {
+        self.distance_squared == other.distance_squared
+    }
+}
+
+impl Eq for Neighbour {}
+
+impl PartialOrd for Neighbour {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbour {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance_squared.total_cmp(&other.distance_squared)
+    }
+}
+
+/// Photons stored in a balanced kd-tree. The median of every range of photons is the
+/// node splitting that range, so the tree needs no pointers
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>, // Splitting axis of the node at each index
+}
+
+impl PhotonMap {
+    pub fn from(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    /// Finds up to `count` photons closest to `p` within `max_radius`, returning their
+    /// indices and the squared radius of the search area
+    fn nearest(&self, p: Point, count: usize, max_radius: f64) -> (Vec<usize>, f64) {
+        let mut heap = BinaryHeap::with_capacity(count + 1);
+        let mut radius_squared = max_radius * max_radius;
+        self.search(
+            0,
+            self.photons.len(),
+            p,
+            count,
+            &mut radius_squared,
+            &mut heap,
+        );
+
+        let indices = heap.into_iter().map(|n| n.index).collect();
+        (indices, radius_squared)
+    }
+
+    fn search(
+        &self,
+        start: usize,
+        end: usize,
+        p: Point,
+        count: usize,
+        radius_squared: &mut f64,
+        heap: &mut BinaryHeap<Neighbour>,
+    ) {
+        if start >= end {
+            return;
+        }
+
+        let mid = (start + end) / 2;
+        let axis = self.axes[mid];
+        let delta = p[axis] - self.photons[mid].p[axis];
+        let (near, far) = match delta < 0.0 {
+            true => ((start, mid), (mid + 1, end)),
+            false => ((mid + 1, end), (start, mid)),
+        };
+
+        self.search(near.0, near.1, p, count, radius_squared, heap);
+
+        let distance_squared = (self.photons[mid].p - p).length_squared();
+        if distance_squared < *radius_squared {
+            heap.push(Neighbour {
+                distance_squared,
+                index: mid,
+            });
+            if heap.len() > count {
+                heap.pop();
+            }
+            // Once enough photons are found, only closer ones are of interest
+            if heap.len() == count {
+                *radius_squared = heap.peek().unwrap().distance_squared;
+            }
+        }
+
+        if delta * delta < *radius_squared {
+            self.search(far.0, far.1, p, count, radius_squared, heap);
+        }
+    }
+
+    /// Estimates the light reflected along `-r.dir` at a hit from the density of the
+    /// `count` photons closest to it
+    pub fn radiance(&self, r: &Ray, rec: &HitRecord, count: usize, max_radius: f64) -> Color {
+        let (indices, radius_squared) = self.nearest(rec.p, count, max_radius);
+        if indices.is_empty() {
+            return Color::new();
+        }
+
+        let mut flux = Color::new();
+        for index in indices {
+            let photon = &self.photons[index];
+            // Photons on the back of the surface light the other side
+            let cosine = -vec3::dot(&vec3::unit_vector(photon.dir), &rec.normal);
+            if cosine <= 0.0 {
+                continue;
+            }
+            let f = rec.mat.eval(r, rec, -photon.dir) / cosine;
+            flux = flux + f * photon.power;
+        }
+        flux / (PI * radius_squared)
+    }
+}
+
+// Arranges the photons so that the median of every range splits it along the axis
+// where the range is widest
+fn build(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.is_empty() {
+        return;
+    }
+
+    let extent = |axis: usize| {
+        let values = photons.iter().map(|photon| photon.p[axis]);
+        let max = values.clone().fold(f64::NEG_INFINITY, f64::max);
+        let min = values.fold(f64::INFINITY, f64::min);
+        max - min
+    };
+    let axis = (0..3)
+        .max_by(|&a, &b| extent(a).total_cmp(&extent(b)))
+        .unwrap();
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+    axes[mid] = axis;
+
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+/// Emits `emitted` photons from the lights and follows them through the scene. With
+/// `caustics` only photons that reach a non-specular surface after specular bounces
+/// straight from the light are stored, otherwise photons are stored at every
+/// non-specular surface they hit. The photons are split between `threads` threads
+pub fn trace_photons(
+    world: &World,
+    emitted: usize,
+    caustics: bool,
+    max_depth: i32,
+    threads: usize,
+) -> Vec<Photon> {
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                // Share out the remainder one photon each to the first threads
+                let count = emitted / threads + usize::from(i < emitted % threads);
+                s.spawn(move || emit_photons(world, count, emitted, caustics, max_depth))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+// Traces `count` of the `emitted` photons of `trace_photons`
+fn emit_photons(
+    world: &World,
+    count: usize,
+    emitted: usize,
+    caustics: bool,
+    max_depth: i32,
+) -> Vec<Photon> {
+    let mut photons = Vec::new();
+
+    for _ in 0..count {
+        let (rec, pdf_pos) = match world.lights.sample_point() {
+            Some(sample) => sample,
+            None => break,
+        };
+
+        let direction = hittable::random_emission_direction(rec.normal);
+        let cosine = vec3::dot(&vec3::unit_vector(direction), &rec.normal).abs();
+        let pdf_dir = hittable::emission_pdf(cosine);
+        if pdf_dir <= 0.0 {
+            continue;
+        }
+        let mut ray = Ray::from(rec.p, direction, 0.0);
+        let emission = rec.mat.emitted(&ray, &rec);
+        let mut power = emission * (cosine / (pdf_pos * pdf_dir * emitted as f64));
+        let mut specular_path = true;
+
+        for depth in 0..max_depth {
+            let rec = match world.objects.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => break,
+            };
+            let srec = match rec.mat.scatter(&ray, &rec) {
+                Some(srec) => srec,
+                None => break,
+            };
+
+            if srec.pdf.is_some() {
+                // Photons are only stored on surfaces, not inside volumes
+                let on_surface = !rec.normal.near_zero();
+                if on_surface && (!caustics || (specular_path && depth > 0)) {
+                    photons.push(Photon {
+                        p: rec.p,
+                        dir: ray.dir,
+                        power,
+                    });
+                }
+                if caustics {
+                    break;
+                }
+                specular_path = false;
+            }
+
+            // Russian roulette keeps the power of surviving photons roughly constant
+            let attenuation = match srec.pdf {
+                Some(pdf) => adjoint_attenuation(&ray, &rec, srec.ray.dir, pdf),
+                None => srec.attenuation,
+            };
+            let survival = attenuation.x.max(attenuation.y).max(attenuation.z).min(1.0);
+            if random::<f64>() >= survival {
+                break;
+            }
+            power = power * attenuation / survival;
+            ray = srec.ray.inherit(&ray);
+        }
+    }
+
+    photons
+}
+
+// Factor scaling the power of a photon arriving along `r` and scattered towards `dir`
+// with density `pdf`. Materials are written for light travelling towards the camera, so
+// they're evaluated for light coming back along `dir`, with cosines against the
+// geometric normal, as light subpaths in BDPT do. This corrects for shading normals
+// (Veach 1997)
+fn adjoint_attenuation(r: &Ray, rec: &HitRecord, dir: Vec3, pdf: f64) -> Color {
+    let r_rev = r.spawn(rec.p + dir, -dir);
+    let rec_rev = rec.facing(&r_rev);
+    let f = rec.mat.eval(&r_rev, &rec_rev, -r.dir);
+
+    // Scattering inside volumes has no cosines
+    if rec.normal.near_zero() {
+        return f / pdf;
+    }
+    let cos_in = vec3::dot(&vec3::unit_vector(r.dir), &rec.normal).abs();
+    let cos_out = vec3::dot(&vec3::unit_vector(dir), &rec.normal).abs();
+    if cos_in < 1.0e-8 {
+        return Color::new();
+    }
+    f * (cos_out / (cos_in * pdf))
+}
+
+/// Photon mapper following the camera ray through specular surfaces to the first
+/// non-specular one. There direct light is sampled from the lights, caustics are
+/// estimated from a caustic photon map, and the rest of the light is gathered by
+/// following one scattered ray and looking up a global photon map where it lands
+pub struct PhotonMapper {
+    pub max_depth: i32,
+    pub global: PhotonMap,
+    pub caustics: PhotonMap,
+    pub gather_count: usize, // Photons used per radiance estimate
+    pub max_radius: f64,
+}
+
+impl PhotonMapper {
+    pub fn from(
+        world: &World,
+        max_depth: i32,
+        photons: usize,
+        caustic_photons: usize,
+        gather_count: usize,
+        max_radius: f64,
+        threads: usize,
+    ) -> Self {
+        let global = trace_photons(world, photons, false, max_depth, threads);
+        let caustics = trace_photons(world, caustic_photons, true, max_depth, threads);
+        eprintln!(
+            "Stored {} global and {} caustic photons",
+            global.len(),
+            caustics.len()
+        );
+
+        PhotonMapper {
+            max_depth,
+            global: PhotonMap::from(global),
+            caustics: PhotonMap::from(caustics),
+            gather_count,
+            max_radius,
+        }
+    }
+
+    // Light arriving at a non-specular hit along the scattered ray, not counting caustics
+    fn gather(&self, rec: &HitRecord, scattered: Ray, pdf: f64, world: &World) -> Color {
+        let mut throughput = Color::from(1.0, 1.0, 1.0);
+        let mut ray = scattered;
+        let emission_weight =
+            integrator::power_heuristic(pdf, world.lights.pdf_value(rec.p, scattered.dir));
+
+        for depth in 0..self.max_depth {
+            let rec = match world.objects.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => return throughput * world.escaped(&ray),
+            };
+
+            let srec = match rec.mat.scatter(&ray, &rec) {
+                Some(srec) => srec,
+                None => {
+                    // Lights seen through specular surfaces are caustics, which the
+                    // caustic map already accounts for
+                    return match depth {
+                        0 => rec.mat.emitted(&ray, &rec) * emission_weight,
+                        _ => Color::new(),
+                    };
+                }
+            };
+            match srec.pdf {
+                Some(_) => {
+                    let count = self.gather_count;
+                    return throughput * self.global.radiance(&ray, &rec, count, self.max_radius);
+                }
+                None => {
+                    throughput = throughput * srec.attenuation;
+                    ray = srec.ray.inherit(&ray);
+                }
+            }
+        }
+
+        Color::new()
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn li(&self, r: &Ray, world: &World) -> Color {
+        let mut color = Color::new();
+        let mut throughput = Color::from(1.0, 1.0, 1.0);
+        let mut ray = *r;
+
+        for _ in 0..self.max_depth {
+            let rec = match world.objects.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    color = color + throughput * world.escaped(&ray);
+                    break;
+                }
+            };
+
+            color = color + throughput * rec.mat.emitted(&ray, &rec);
+            let srec = match rec.mat.scatter(&ray, &rec) {
+                Some(srec) => srec,
+                None => break,
+            };
+
+            let pdf = match srec.pdf {
+                Some(pdf) => pdf,
+                None => {
+                    throughput = throughput * srec.attenuation;
+                    ray = srec.ray.inherit(&ray);
+                    continue;
+                }
+            };
+
+            let direct = integrator::sample_lights(&ray, &rec, world);
+            let caustics = self
+                .caustics
+                .radiance(&ray, &rec, self.gather_count, self.max_radius);
+            let indirect = srec.attenuation * self.gather(&rec, srec.ray.inherit(&ray), pdf, world);
+            color = color + throughput * (direct + caustics + indirect);
+            break;
+        }
+
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let photons: Vec<Photon> = (0..2000)
+            .map(|_| Photon {
+                p: Point::random_range(-1.0..1.0),
+                dir: Vec3::from(0.0, -1.0, 0.0),
+                power: Color::from(1.0, 1.0, 1.0),
+            })
+            .collect();
+        let map = PhotonMap::from(photons.clone());
+
+        for _ in 0..100 {
+            let p = Point::random_range(-1.2..1.2);
+            for (count, max_radius) in [(1, 10.0), (20, 10.0), (50, 0.2)] {
+                let (indices, radius_squared) = map.nearest(p, count, max_radius);
+                let mut found: Vec<f64> = indices
+                    .iter()
+                    .map(|&i| (map.photons[i].p - p).length_squared())
+                    .collect();
+                found.sort_by(f64::total_cmp);
+
+                let mut expected: Vec<f64> = photons
+                    .iter()
+                    .map(|photon| (photon.p - p).length_squared())
+                    .filter(|&d| d < max_radius * max_radius)
+                    .collect();
+                expected.sort_by(f64::total_cmp);
+                expected.truncate(count);
+
+                assert_eq!(found, expected);
+                let farthest = expected.last().copied();
+                match expected.len() == count {
+                    true => assert_eq!(Some(radius_squared), farthest),
+                    false => assert_eq!(radius_squared, max_radius * max_radius),
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,40 @@
-use crate::vec3::{Point, Vec3};
+use crate::vec3::{self, Point, Vec3};
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 pub struct Ray {
     pub orig: Point,
     pub dir: Vec3,
     pub t: f64,
+    pub lambda: Option<Vec3>, // Wavelengths carried in spectral mode
+    pub hero: Option<usize>,  // Channel followed alone after dispersion in RGB mode
+    pub differentials: Option<Differentials>,
+}
+
+/// Rays through the neighbouring pixels in x and y, following a camera ray to tell how
+/// much of the scene one pixel covers
+#[derive(Debug, Copy, Clone)]
+pub struct Differentials {
+    pub rx_orig: Point,
+    pub rx_dir: Vec3,
+    pub ry_orig: Point,
+    pub ry_dir: Vec3,
+}
+
+impl Differentials {
+    /// Points where the offset rays cross the plane through `p` with normal `n`
+    pub fn at_plane(&self, p: Point, n: Vec3) -> Option<(Point, Point)> {
+        let cross = |orig: Point, dir: Vec3| {
+            let t = vec3::dot(&n, &(p - orig)) / vec3::dot(&n, &dir);
+            match t.is_finite() {
+                true => Some(orig + t * dir),
+                false => None,
+            }
+        };
+        Some((
+            cross(self.rx_orig, self.rx_dir)?,
+            cross(self.ry_orig, self.ry_dir)?,
+        ))
+    }
 }
 
 impl Ray {
@@ -13,6 +43,29 @@ impl Ray {
             orig: origin,
             dir: direction,
             t: time,
+            lambda: None,
+            hero: None,
+            differentials: None,
+        }
+    }
+
+    /// Ray at the same time and wavelengths as this one
+    pub fn spawn(&self, origin: Point, direction: Vec3) -> Ray {
+        Ray {
+            orig: origin,
+            dir: direction,
+            differentials: None,
+            ..*self
+        }
+    }
+
+    /// Takes the wavelengths and hero channel of `parent`, unless this ray already
+    /// carries its own
+    pub fn inherit(self, parent: &Ray) -> Ray {
+        Ray {
+            lambda: self.lambda.or(parent.lambda),
+            hero: self.hero.or(parent.hero),
+            ..self
         }
     }
 
@@ -0,0 +1,213 @@
+use rand::prelude::*;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::camera::Camera;
+use crate::film::{Film, Pixel};
+use crate::integrator::{Integrator, World};
+use crate::options::Options;
+use crate::scheduler::{self, TileTiming, WorkQueue};
+use crate::spectrum;
+use crate::tile::{self, Tile};
+
+pub struct Renderer<'a> {
+    pub camera: &'a Camera,
+    pub world: World<'a>,
+    pub integrator: &'a dyn Integrator,
+    pub image_width: i32,
+    pub image_height: i32,
+    pub options: &'a Options,
+}
+
+impl Renderer<'_> {
+    fn render_tile(&self, tile: &Tile, budget: &[u32]) -> Vec<Pixel> {
+        let mut pixels = Vec::with_capacity(tile.width() * tile.height());
+        // Samples spread over the pixel, so each covers less of it than a whole pixel
+        let spread = (1.0 / (self.options.samples as f64).sqrt()).max(0.125);
+        let du = spread / (self.image_width - 1) as f64;
+        let dv = spread / (self.image_height - 1) as f64;
+        for y in tile.y0..tile.y1 {
+            // Image rows are stored top to bottom, but v increases upwards
+            let j = self.image_height - 1 - y as i32;
+            for i in tile.x0 as i32..tile.x1 as i32 {
+                let mut pixel = Pixel::default();
+                for _ in 0..budget[y * self.image_width as usize + i as usize] {
+                    let u = (i as f64 + random::<f64>()) / (self.image_width - 1) as f64;
+                    let v = (j as f64 + random::<f64>()) / (self.image_height - 1) as f64;
+                    let mut r = self.camera.get_ray(u, v, du, dv);
+                    let color = match self.options.spectral {
+                        true => {
+                            let lambda = spectrum::sample_wavelengths();
+                            r.lambda = Some(lambda);
+                            spectrum::to_rgb(self.integrator.li(&r, &self.world), lambda)
+                        }
+                        false => self.integrator.li(&r, &self.world),
+                    };
+                    pixel.add_sample(color);
+                }
+                pixels.push(pixel);
+            }
+        }
+        pixels
+    }
+
+    /// Number of samples each pixel should receive in the next pass
+    fn sample_budget(&self, film: &Film) -> Vec<u32> {
+        film.pixels
+            .iter()
+            .map(|pixel| pixel_budget(pixel, self.options))
+            .collect()
+    }
+
+    /// Adds the number of samples given by `budget` to every pixel of the film, rendering
+    /// tile by tile on `options.threads` threads
+    fn render_pass(&self, film: &mut Film, budget: &[u32], label: &str) -> Vec<TileTiming> {
+        let film = Mutex::new(film);
+        let timings = Mutex::new(Vec::new());
+
+        let tiles = tile::tiles(
+            self.image_width as usize,
+            self.image_height as usize,
+            self.options.tile_size,
+            self.options.tile_order,
+        );
+        let queue = WorkQueue::from(tiles.len(), self.options.threads);
+        let tiles_done = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for worker in 0..self.options.threads {
+                let film = &film;
+                let timings = &timings;
+                let tiles = &tiles;
+                let queue = &queue;
+                let tiles_done = &tiles_done;
+
+                s.spawn(move || {
+                    while let Some((index, stolen)) = queue.next(worker) {
+                        let tile = tiles[index];
+                        let start = Instant::now();
+                        let pixels = self.render_tile(&tile, budget);
+                        let duration = start.elapsed();
+
+                        film.lock().unwrap().add_tile(&tile, &pixels);
+                        timings.lock().unwrap().push(TileTiming {
+                            index,
+                            tile,
+                            worker,
+                            stolen,
+                            duration,
+                        });
+
+                        let done = tiles_done.fetch_add(1, Ordering::Relaxed) + 1;
+                        eprint!("\r{label}Tiles remaining: {:04}", tiles.len() - done);
+                    }
+                });
+            }
+        });
+        eprintln!();
+
+        timings.into_inner().unwrap()
+    }
+
+    /// Renders until every pixel of the film has `options.samples` samples, or until
+    /// adaptive sampling considers every pixel converged. Progressive and adaptive renders
+    /// are split into passes of `options.pass_samples` samples, and progressive renders
+    /// write the image and a checkpoint every `options.checkpoint_interval` seconds
+    pub fn render(&self, film: &mut Film) -> io::Result<Vec<TileTiming>> {
+        let interval = Duration::from_secs(self.options.checkpoint_interval);
+
+        let mut timings = Vec::new();
+        let mut last_checkpoint = Instant::now();
+        let mut pass = 0;
+
+        loop {
+            let budget = self.sample_budget(film);
+            let active = budget.iter().filter(|&&n| n > 0).count();
+            if active == 0 {
+                break;
+            }
+
+            pass += 1;
+            let label = match self.options.progressive || self.options.adaptive {
+                true => format!("Pass {pass}, {active} pixels: "),
+                false => String::new(),
+            };
+            scheduler::accumulate(&mut timings, self.render_pass(film, &budget, &label));
+
+            if self.options.progressive && last_checkpoint.elapsed() >= interval {
+                film.write_ppm(&self.options.output)?;
+                film.save_checkpoint(&self.options.checkpoint)?;
+                last_checkpoint = Instant::now();
+            }
+        }
+
+        Ok(timings)
+    }
+}
+
+// Number of samples a pixel should receive in the next pass. Adaptive renders stop
+// sampling pixels whose noise estimate has fallen below the threshold
+fn pixel_budget(pixel: &Pixel, options: &Options) -> u32 {
+    let max_samples = options.samples;
+    let min_samples = options.min_samples.min(max_samples);
+    let remaining = max_samples.saturating_sub(pixel.samples);
+    if options.adaptive {
+        if pixel.samples < min_samples {
+            min_samples - pixel.samples
+        } else if pixel.error() < options.noise_threshold {
+            0
+        } else {
+            remaining.min(options.pass_samples)
+        }
+    } else if options.progressive {
+        remaining.min(options.pass_samples)
+    } else {
+        remaining
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::Color;
+
+    #[test]
+    fn adaptive_sampling_stops_converged_pixels() {
+        let mut options = Options::new();
+        options.adaptive = true;
+        options.samples = 256;
+        options.min_samples = 16;
+
+        // A constant pixel, as on a flat lit wall, and one alternating between black and
+        // white, as on a noisy edge
+        let mut film = Film::from(2, 1);
+        let mut passes = 0;
+        loop {
+            let budget: Vec<u32> = film
+                .pixels
+                .iter()
+                .map(|pixel| pixel_budget(pixel, &options))
+                .collect();
+            if budget.iter().all(|&n| n == 0) {
+                break;
+            }
+            for _ in 0..budget[0] {
+                film.pixels[0].add_sample(Color::from(0.5, 0.5, 0.5));
+            }
+            for _ in 0..budget[1] {
+                let value = (film.pixels[1].samples % 2) as f64;
+                film.pixels[1].add_sample(Color::from(value, value, value));
+            }
+            passes += 1;
+        }
+
+        assert_eq!(film.pixels[0].error(), 0.0);
+        assert_eq!(film.pixels[0].samples, options.min_samples);
+        assert!(film.pixels[1].error() > options.noise_threshold);
+        assert_eq!(film.pixels[1].samples, options.samples);
+        assert!(passes > 1);
+    }
+}
@@ -0,0 +1,67 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+
+pub struct Scene {
+    pub objects: HittableList,
+    pub lights: HittableList, // Emissive objects sampled directly by the integrator
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Scene {
+            objects: HittableList::new(),
+            lights: HittableList::new(),
+        }
+    }
+
+    pub fn push(&mut self, object: Arc<dyn Hittable>) {
+        self.objects.push(object);
+    }
+
+    /// Adds an emissive object that is also sampled for direct lighting
+    pub fn push_light(&mut self, light: Arc<dyn Hittable>) {
+        self.objects.push(light.clone());
+        self.lights.push(light);
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SceneKind {
+    Random,
+    SimpleLight,
+    CornellBox,
+    CornellSmoke,
+    Volume,
+    Caustics,
+    Earth,
+    Materials,
+    Principled,
+}
+
+impl FromStr for SceneKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(SceneKind::Random),
+            "simple-light" => Ok(SceneKind::SimpleLight),
+            "cornell" => Ok(SceneKind::CornellBox),
+            "cornell-smoke" => Ok(SceneKind::CornellSmoke),
+            "volume" => Ok(SceneKind::Volume),
+            "caustics" => Ok(SceneKind::Caustics),
+            "earth" => Ok(SceneKind::Earth),
+            "materials" => Ok(SceneKind::Materials),
+            "principled" => Ok(SceneKind::Principled),
+            _ => Err(format!("unknown scene '{s}'")),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::tile::Tile;
+
+/// Per-worker queues of tile indices. Workers take tiles from the front of their own
+/// queue and, once it runs dry, steal from the back of the other queues
+pub struct WorkQueue {
+    queues: Vec<Mutex<VecDeque<usize>>>,
+}
+
+impl WorkQueue {
+    pub fn from(tile_count: usize, workers: usize) -> Self {
+        // Deal the tiles out round-robin so every worker starts near the front of the
+        // requested order
+        let mut queues = vec![VecDeque::new(); workers];
+        for i in 0..tile_count {
+            queues[i % workers].push_back(i);
+        }
+
+        WorkQueue {
+            queues: queues.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
+    /// Returns the index of the next tile for `worker` and whether it was stolen from
+    /// another worker
+    pub fn next(&self, worker: usize) -> Option<(usize, bool)> {
+        if let Some(tile) = self.queues[worker].lock().unwrap().pop_front() {
+            return Some((tile, false));
+        }
+
+        let workers = self.queues.len();
+        (1..workers)
+            .map(|offset| (worker + offset) % workers)
+            .find_map(|victim| self.queues[victim].lock().unwrap().pop_back())
+            .map(|tile| (tile, true))
+    }
+}
+
+pub struct TileTiming {
+    pub index: usize,
+    pub tile: Tile,
+    pub worker: usize,
+    pub stolen: bool,
+    pub duration: Duration,
+}
+
+/// Adds the timings of a render pass to the running totals of each tile
+pub fn accumulate(totals: &mut Vec<TileTiming>, pass: Vec<TileTiming>) {
+    if totals.is_empty() {
+        *totals = pass;
+        totals.sort_by_key(|t| t.index);
+        return;
+    }
+
+    for t in pass {
+        let total = &mut totals[t.index];
+        total.duration += t.duration;
+        total.stolen |= t.stolen;
+    }
+}
+
+/// Prints a summary of the slowest tiles to stderr
+pub fn report_timings(timings: &[TileTiming], count: usize) {
+    let total: Duration = timings.iter().map(|t| t.duration).sum();
+    let stolen = timings.iter().filter(|t| t.stolen).count();
+    eprintln!(
+        "{} tiles, {:.2}s total render time, {} stolen",
+        timings.len(),
+        total.as_secs_f64(),
+        stolen
+    );
+
+    let mut slowest: Vec<&TileTiming> = timings.iter().collect();
+    slowest.sort_by_key(|t| Reverse(t.duration));
+    for t in slowest.iter().take(count) {
+        eprintln!(
+            "  tile x {}..{} y {}..{}: {:.1} ms",
+            t.tile.x0,
+            t.tile.x1,
+            t.tile.y0,
+            t.tile.y1,
+            t.duration.as_secs_f64() * 1000.0
+        );
+    }
+}
+
+/// Writes the timing of every tile to a CSV file
+pub fn write_timings(path: &str, timings: &[TileTiming]) -> io::Result<()> {
+    let f = File::create(path)?;
+    let mut writer = BufWriter::new(f);
+
+    writer.write_all(b"x0,x1,y0,y1,worker,stolen,ms\n")?;
+    for t in timings {
+        let line = format!(
+            "{},{},{},{},{},{},{:.3}\n",
+            t.tile.x0,
+            t.tile.x1,
+            t.tile.y0,
+            t.tile.y1,
+            t.worker,
+            t.stolen,
+            t.duration.as_secs_f64() * 1000.0
+        );
+        writer.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
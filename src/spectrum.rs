@@ -0,0 +1,196 @@
+use rand::prelude::*;
+use std::sync::OnceLock;
+
+use crate::ray::Ray;
+use crate::vec3::{Color, Vec3};
+
+/// Range of wavelengths in nanometres sampled in spectral mode
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 720.0;
+
+/// Wavelengths standing for the red, green and blue channels in RGB mode
+pub const RGB_WAVELENGTHS: [f64; 3] = [612.0, 549.0, 465.0];
+
+// Smits' basis spectra for converting RGB to spectra, at ten evenly spaced wavelengths
+// across the sampled range
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Samples three wavelengths for a camera ray, evenly spread over the range from a
+/// random hero wavelength
+pub fn sample_wavelengths() -> Vec3 {
+    let u = random::<f64>();
+    let lambda = |i: f64| LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * ((u + i / 3.0) % 1.0);
+    Vec3::from(lambda(0.0), lambda(1.0), lambda(2.0))
+}
+
+// Value of a basis spectrum at `lambda`, interpolated linearly
+fn basis(spectrum: &[f64; 10], lambda: f64) -> f64 {
+    let x = ((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 9.0).clamp(0.0, 9.0);
+    let i = (x.floor() as usize).min(8);
+    let t = x - i as f64;
+    (1.0 - t) * spectrum[i] + t * spectrum[i + 1]
+}
+
+/// Value at `lambda` of a smooth spectrum with the given RGB color, using Smits' method
+pub fn rgb_to_spectrum(rgb: Color, lambda: f64) -> f64 {
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+    let at = |spectrum| basis(spectrum, lambda);
+
+    if r <= g && r <= b {
+        r * at(&SMITS_WHITE)
+            + match g <= b {
+                true => (g - r) * at(&SMITS_CYAN) + (b - g) * at(&SMITS_BLUE),
+                false => (b - r) * at(&SMITS_CYAN) + (g - b) * at(&SMITS_GREEN),
+            }
+    } else if g <= r && g <= b {
+        g * at(&SMITS_WHITE)
+            + match r <= b {
+                true => (r - g) * at(&SMITS_MAGENTA) + (b - r) * at(&SMITS_BLUE),
+                false => (b - g) * at(&SMITS_MAGENTA) + (r - b) * at(&SMITS_RED),
+            }
+    } else {
+        b * at(&SMITS_WHITE)
+            + match r <= g {
+                true => (r - b) * at(&SMITS_YELLOW) + (g - r) * at(&SMITS_GREEN),
+                false => (g - b) * at(&SMITS_YELLOW) + (r - g) * at(&SMITS_RED),
+            }
+    }
+}
+
+/// An RGB color as seen along `r`. Rays carrying wavelengths get the values of the
+/// color's spectrum at them, so the components of the result follow `r.lambda`
+pub fn color_at(rgb: Color, r: &Ray) -> Color {
+    match r.lambda {
+        Some(lambda) => Color::from(
+            rgb_to_spectrum(rgb, lambda.x),
+            rgb_to_spectrum(rgb, lambda.y),
+            rgb_to_spectrum(rgb, lambda.z),
+        ),
+        None => rgb,
+    }
+}
+
+// Piecewise Gaussian used by the analytic fit of the color matching functions
+fn gaussian(x: f64, mu: f64, sigma1: f64, sigma2: f64) -> f64 {
+    let t = match x < mu {
+        true => (x - mu) / sigma1,
+        false => (x - mu) / sigma2,
+    };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 color matching functions at `lambda`, from the multi-lobe fit by Wyman,
+/// Sloan and Shirley
+pub fn xyz_matching(lambda: f64) -> Vec3 {
+    let x = 1.056 * gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2);
+    let y =
+        0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1);
+    let z =
+        1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8);
+    Vec3::from(x, y, z)
+}
+
+/// Linear sRGB from CIE XYZ
+pub fn xyz_to_rgb(xyz: Vec3) -> Color {
+    Color::from(
+        3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+        -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+        0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z,
+    )
+}
+
+// Color of a constant spectrum of 1 over the sampled range, before white balancing
+fn white_point() -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let steps = 1000;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        let xyz = (0..steps)
+            .map(|i| xyz_matching(LAMBDA_MIN + (i as f64 + 0.5) * step) * step)
+            .fold(Vec3::new(), |a, b| a + b);
+        xyz_to_rgb(xyz)
+    })
+}
+
+/// Converts radiance sampled at the wavelengths `lambda` to RGB, white balanced so that
+/// a constant spectrum comes out grey
+pub fn to_rgb(radiance: Color, lambda: Vec3) -> Color {
+    let mut xyz = Vec3::new();
+    for i in 0..3 {
+        xyz = xyz + radiance[i] * xyz_matching(lambda[i]);
+    }
+    // Monte Carlo estimate of the integral over the range, with uniform wavelengths
+    let xyz = xyz * ((LAMBDA_MAX - LAMBDA_MIN) / 3.0);
+
+    let rgb = xyz_to_rgb(xyz);
+    let white = white_point();
+    Color::from(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_is_one_at_every_wavelength() {
+        let white = Color::from(1.0, 1.0, 1.0);
+        for i in 0..=340 {
+            let lambda = LAMBDA_MIN + i as f64;
+            let mut r = Ray::from(Vec3::new(), Vec3::from(0.0, 0.0, 1.0), 0.0);
+            r.lambda = Some(Vec3::from(lambda, lambda, lambda));
+            let value = color_at(white, &r);
+            assert!((value.x - 1.0).abs() < 1e-3, "{lambda} nm: {}", value.x);
+        }
+    }
+
+    #[test]
+    fn matching_functions_integrate_to_the_cie_tables() {
+        // An equal-energy spectrum over the visible range gives X = Y = Z = 106.857
+        // with the tabulated functions at 1 nm steps
+        let xyz = (360..830)
+            .map(|lambda| xyz_matching(lambda as f64 + 0.5))
+            .fold(Vec3::new(), |a, b| a + b);
+        for value in [xyz.x, xyz.y, xyz.z] {
+            assert!((value / 106.857 - 1.0).abs() < 5e-3, "{value}");
+        }
+    }
+
+    #[test]
+    fn constant_spectrum_comes_out_white() {
+        // Averaging over evenly spread hero wavelengths integrates over the range
+        let steps = 1000;
+        let mut sum = Color::new();
+        for i in 0..steps {
+            let u = (i as f64 + 0.5) / steps as f64;
+            let lambda = |k: f64| LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * ((u + k / 3.0) % 1.0);
+            let lambda = Vec3::from(lambda(0.0), lambda(1.0), lambda(2.0));
+            sum = sum + to_rgb(Color::from(1.0, 1.0, 1.0), lambda);
+        }
+        let rgb = sum / steps as f64;
+        for value in [rgb.x, rgb.y, rgb.z] {
+            assert!((value - 1.0).abs() < 1e-3, "{value}");
+        }
+    }
+}
@@ -1,20 +1,23 @@
-use std::rc::Rc;
+use rand::prelude::*;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
-use crate::vec3::{self, Point};
+use crate::vec3::{self, Point, Vec3};
 
 pub struct Sphere {
     center: Point,
     radius: f64,
-    mat: Rc<dyn Material>,
+    mat: Arc<dyn Material>,
 }
 
 impl Sphere {
     #[allow(dead_code)]
-    pub fn from(center: Point, radius: f64, mat: Rc<dyn Material>) -> Self {
+    pub fn from(center: Point, radius: f64, mat: Arc<dyn Material>) -> Self {
         Sphere {
             center,
             radius,
@@ -49,7 +52,13 @@ impl Hittable for Sphere {
         let p = r.at(t);
         let outward_normal = (p - self.center) / self.radius;
 
-        Some(HitRecord::from(r, p, t, outward_normal, self.mat.clone()))
+        let (u, v) = sphere_uv(outward_normal);
+
+        let (dpdu, dpdv) = sphere_tangents(outward_normal, self.radius);
+        Some(
+            HitRecord::from(r, p, t, u, v, outward_normal, self.mat.clone())
+                .with_tangents(dpdu, dpdv),
+        )
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
@@ -58,4 +67,86 @@ impl Hittable for Sphere {
             self.center + Point::from(self.radius, self.radius, self.radius),
         ))
     }
+
+    fn materials(&self) -> Vec<Arc<dyn Material>> {
+        vec![self.mat.clone()]
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * std::f64::consts::PI * self.radius * self.radius
+    }
+
+    fn sample_area(&self) -> Option<HitRecord> {
+        let normal = Vec3::random_unit_vector();
+        let (u, v) = sphere_uv(normal);
+        let (dpdu, dpdv) = sphere_tangents(normal, self.radius);
+        Some(
+            HitRecord::on_surface(
+                self.center + self.radius * normal,
+                u,
+                v,
+                normal,
+                self.mat.clone(),
+            )
+            .with_tangents(dpdu, dpdv),
+        )
+    }
+
+    fn pdf_value(&self, origin: Point, v: Vec3) -> f64 {
+        // From inside, every direction reaches the surface once, as area sampling expects
+        let distance_squared = (self.center - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return self.area_pdf_value(origin, v);
+        }
+
+        match self.hit(&Ray::from(origin, v, 0.0), 0.001, f64::INFINITY) {
+            Some(_) => {
+                let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+                1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point) -> Vec3 {
+        // Outside the sphere, sample the cone of directions it covers
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return self.center + self.radius * Vec3::random_unit_vector() - origin;
+        }
+        let uvw = Onb::build_from_w(direction);
+        uvw.local(random_to_sphere(self.radius, distance_squared))
+    }
+}
+
+// Uniform direction in the cone around +z covering a sphere of `radius` at a squared
+// distance of `distance_squared`
+fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+    let r1 = random::<f64>();
+    let r2 = random::<f64>();
+    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+
+    let phi = 2.0 * PI * r1;
+    let x = phi.cos() * (1.0 - z * z).sqrt();
+    let y = phi.sin() * (1.0 - z * z).sqrt();
+    Vec3::from(x, y, z)
+}
+
+/// Maps a point on the unit sphere to (u, v) in [0, 1], with u running around the Y
+/// axis from X=-1 and v from Y=-1 to Y=+1
+pub fn sphere_uv(p: Point) -> (f64, f64) {
+    let theta = (-p.y).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+/// Derivatives of the point at `n` on a sphere of `radius` along the (u, v) of `sphere_uv`
+pub fn sphere_tangents(n: Vec3, radius: f64) -> (Vec3, Vec3) {
+    // Distance from the Y axis, kept away from zero at the poles
+    let rho = (n.x * n.x + n.z * n.z).sqrt().max(1e-8);
+    let dpdu = 2.0 * PI * radius * Vec3::from(n.z, 0.0, -n.x);
+    let dpdv = PI * radius * Vec3::from(-n.y * n.x / rho, rho, -n.y * n.z / rho);
+    (dpdu, dpdv)
 }
@@ -0,0 +1,180 @@
+use std::io;
+use std::sync::Arc;
+
+use crate::image::{Image, MipMap, Wrap};
+use crate::perlin::Perlin;
+use crate::vec3::{Color, Point};
+
+/// Color varying over a surface, looked up by the surface coordinates (u, v) and the
+/// point `p` of a hit
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: Point) -> Color;
+
+    /// Color averaged over about `width` around (u, v) in surface coordinates, to avoid
+    /// aliasing where one sample covers much of the texture
+    fn filtered(&self, u: f64, v: f64, p: Point, _width: f64) -> Color {
+        self.value(u, v, p)
+    }
+}
+
+pub struct SolidColor {
+    pub color: Color,
+}
+
+impl SolidColor {
+    pub fn from(color: Color) -> Self {
+        SolidColor { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point) -> Color {
+        self.color
+    }
+}
+
+/// Checkerboard of cubes with edge length `scale`, alternating between two textures
+pub struct Checker {
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+    pub scale: f64,
+}
+
+impl Checker {
+    #[allow(dead_code)]
+    pub fn from(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: f64) -> Self {
+        Checker { even, odd, scale }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_colors(even: Color, odd: Color, scale: f64) -> Self {
+        Checker {
+            even: Arc::new(SolidColor::from(even)),
+            odd: Arc::new(SolidColor::from(odd)),
+            scale,
+        }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, p: Point) -> Color {
+        let cell = |x: f64| (x / self.scale).floor() as i64;
+        match (cell(p.x) + cell(p.y) + cell(p.z)) % 2 == 0 {
+            true => self.even.value(u, v, p),
+            false => self.odd.value(u, v, p),
+        }
+    }
+
+    fn filtered(&self, u: f64, v: f64, p: Point, width: f64) -> Color {
+        let cell = |x: f64| (x / self.scale).floor() as i64;
+        match (cell(p.x) + cell(p.y) + cell(p.z)) % 2 == 0 {
+            true => self.even.filtered(u, v, p, width),
+            false => self.odd.filtered(u, v, p, width),
+        }
+    }
+}
+
+/// Image stretched over the surface coordinates, with (0, 0) at the bottom left corner
+pub struct ImageTexture {
+    pub mipmap: Arc<MipMap>,
+    pub wrap: Wrap,
+}
+
+impl ImageTexture {
+    #[allow(dead_code)]
+    pub fn from(image: Image, wrap: Wrap) -> Self {
+        ImageTexture {
+            mipmap: Arc::new(MipMap::from(image)),
+            wrap,
+        }
+    }
+
+    /// The same image wrapped another way, sharing its mipmap
+    pub fn with_wrap(&self, wrap: Wrap) -> Self {
+        ImageTexture {
+            mipmap: self.mipmap.clone(),
+            wrap,
+        }
+    }
+
+    /// Loads the texture from a PPM file
+    #[allow(dead_code)]
+    pub fn load(path: &str, wrap: Wrap) -> io::Result<Self> {
+        Ok(Self::from(Image::load_ppm(path)?, wrap))
+    }
+
+    /// Loads a texture of data, like a normal map, from a PPM file without converting
+    /// its values from sRGB
+    #[allow(dead_code)]
+    pub fn load_linear(path: &str, wrap: Wrap) -> io::Result<Self> {
+        Ok(Self::from(Image::load_ppm_linear(path)?, wrap))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Point) -> Color {
+        self.mipmap.image().bilinear(u, v, self.wrap)
+    }
+
+    fn filtered(&self, u: f64, v: f64, _p: Point, width: f64) -> Color {
+        self.mipmap.trilinear(u, v, width, self.wrap)
+    }
+}
+
+/// Procedural patterns made from Perlin noise
+#[allow(dead_code)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NoisePattern {
+    Smooth,
+    Turbulence,
+    Fbm,
+    Marble, // Veins along Z distorted by turbulence
+    Wood,   // Rings around the Y axis distorted by turbulence
+}
+
+/// Blend between two colors following a noise pattern at the hit point, with features
+/// about `1 / scale` apart
+pub struct NoiseTexture {
+    noise: Perlin,
+    pub pattern: NoisePattern,
+    pub scale: f64,
+    pub low: Color,
+    pub high: Color,
+}
+
+impl NoiseTexture {
+    #[allow(dead_code)]
+    pub fn from(pattern: NoisePattern, scale: f64, low: Color, high: Color) -> Self {
+        NoiseTexture {
+            noise: Perlin::new(),
+            pattern,
+            scale,
+            low,
+            high,
+        }
+    }
+
+    // Pattern value at `p`, in [0, 1]
+    fn blend(&self, p: Point) -> f64 {
+        let q = self.scale * p;
+        let value = match self.pattern {
+            NoisePattern::Smooth => 0.5 * (1.0 + self.noise.noise(q)),
+            NoisePattern::Turbulence => self.noise.turb(q, 7),
+            NoisePattern::Fbm => 0.5 * (1.0 + self.noise.fbm(q, 7)),
+            // The veins are distorted at a coarser scale than they're spaced
+            NoisePattern::Marble => 0.5 * (1.0 + (q.z + 10.0 * self.noise.turb(p, 7)).sin()),
+            NoisePattern::Wood => {
+                let rings = 4.0 * (q.x * q.x + q.z * q.z).sqrt() + 2.0 * self.noise.turb(q, 4);
+                rings - rings.floor()
+            }
+        };
+        value.clamp(0.0, 1.0)
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point) -> Color {
+        let t = self.blend(p);
+        (1.0 - t) * self.low + t * self.high
+    }
+}
@@ -0,0 +1,176 @@
+use std::str::FromStr;
+
+/// A rectangular block of pixels in image coordinates, where row 0 is the top of the
+/// image. The upper bounds are exclusive
+#[derive(Debug, Copy, Clone)]
+pub struct Tile {
+    pub x0: usize,
+    pub x1: usize,
+    pub y0: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    /// Follows a Moore curve, the variant of the Hilbert curve that closes into a loop,
+    /// so that it can start at the center of the image
+    Hilbert,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "spiral" => Ok(TileOrder::Spiral),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            _ => Err(format!("unknown tile order '{s}'")),
+        }
+    }
+}
+
+/// Splits the image into tiles of at most `tile_size` x `tile_size` pixels, listed in
+/// the order they should be rendered
+pub fn tiles(width: usize, height: usize, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+    let nx = width.div_ceil(tile_size);
+    let ny = height.div_ceil(tile_size);
+
+    let grid = match order {
+        TileOrder::Scanline => (0..ny)
+            .flat_map(|ty| (0..nx).map(move |tx| (tx, ty)))
+            .collect(),
+        TileOrder::Spiral => spiral_order(nx, ny),
+        TileOrder::Hilbert => moore_order(nx, ny),
+    };
+
+    grid.into_iter()
+        .map(|(tx, ty)| Tile {
+            x0: tx * tile_size,
+            x1: ((tx + 1) * tile_size).min(width),
+            y0: ty * tile_size,
+            y1: ((ty + 1) * tile_size).min(height),
+        })
+        .collect()
+}
+
+// Walks a square spiral outwards from the center tile, skipping positions that fall
+// outside the grid
+fn spiral_order(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let total = nx * ny;
+    let mut order = Vec::with_capacity(total);
+    let (mut x, mut y) = (((nx - 1) / 2) as i64, ((ny - 1) / 2) as i64);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 0;
+
+    while order.len() < total {
+        // Every two legs of the spiral grow one step longer
+        let (dx, dy) = directions[leg % 4];
+        let steps = leg / 2 + 1;
+        for _ in 0..steps {
+            if x >= 0 && y >= 0 && (x as usize) < nx && (y as usize) < ny {
+                order.push((x as usize, y as usize));
+            }
+            x += dx;
+            y += dy;
+        }
+        leg += 1;
+    }
+    order
+}
+
+// Follows a Moore curve, a loop of four Hilbert curves, over the smallest power-of-two
+// square covering the grid centered in it. The loop passes through the middle of the
+// square, so the order starts there and goes round it back to the start
+fn moore_order(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let n = nx.max(ny).next_power_of_two().max(2);
+    let m = n / 2;
+    let (ox, oy) = ((n - nx) / 2, (n - ny) / 2);
+    (0..n * n)
+        .map(|i| {
+            // Start at the end of the first quadrant, next to the middle
+            let d = (i + m * m - 1) % (n * n);
+            let (x, y) = hilbert_d2xy(m, d % (m * m));
+            match d / (m * m) {
+                0 => (m - 1 - y, x),
+                1 => (m - 1 - y, m + x),
+                2 => (m + y, n - 1 - x),
+                _ => (m + y, m - 1 - x),
+            }
+        })
+        .filter(|&(x, y)| x >= ox && y >= oy && x - ox < nx && y - oy < ny)
+        .map(|(x, y)| (x - ox, y - oy))
+        .collect()
+}
+
+// Converts a distance along the Hilbert curve to a position on an n x n grid
+fn hilbert_d2xy(n: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        // Rotate the quadrant
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_order_lists_every_tile_once() {
+        let orders = [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert];
+        for (width, height) in [(5, 3), (7, 1), (1, 7), (4, 4), (9, 6)] {
+            for order in orders {
+                let mut seen = vec![0; width * height];
+                for tile in tiles(width, height, 1, order) {
+                    seen[tile.y0 * width + tile.x0] += 1;
+                }
+                assert!(
+                    seen.iter().all(|&count| count == 1),
+                    "{order:?} on {width}x{height}: {seen:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_cover_partial_edges() {
+        let tiles = tiles(10, 5, 4, TileOrder::Scanline);
+        assert_eq!(tiles.len(), 6);
+        let area: usize = tiles.iter().map(|t| t.width() * t.height()).sum();
+        assert_eq!(area, 50);
+    }
+
+    #[test]
+    fn hilbert_order_starts_at_the_center() {
+        let (x, y) = moore_order(8, 8)[0];
+        assert!((3..=4).contains(&x) && (3..=4).contains(&y));
+    }
+}